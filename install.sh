@@ -44,6 +44,8 @@ if [ $? -ne 0 ]; then exit 1; fi
 echo 'Step 5: Copy over more files'
 $ROOT_CMD cp -f "extra/xsetup.sh" "/etc/lemurs/xsetup.sh"
 if [ $? -ne 0 ]; then exit 1; fi
+$ROOT_CMD cp -f "extra/lemurs.pam" "/etc/pam.d/lemurs"
+if [ $? -ne 0 ]; then exit 1; fi
 
 # Copy over default xinitrc
 if [ -f .xinitrc ]
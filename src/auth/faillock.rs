@@ -0,0 +1,204 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::info_caching::check_username;
+
+pub const FAILLOCK_CONF_PATH: &str = "/etc/security/faillock.conf";
+
+/// The size of a `struct tally` record in a tally file of `pam_faillock`
+const TALLY_SIZE: usize = 64;
+/// The offset of the `status` and `time` fields within a record
+const TALLY_STATUS_OFFSET: usize = 54;
+const TALLY_TIME_OFFSET: usize = 56;
+/// The flag of the `status` field for records that count as a failure
+const TALLY_STATUS_VALID: u16 = 0x1;
+
+/// The options of `faillock.conf` that decide whether an account is locked
+#[derive(Debug, PartialEq, Eq)]
+struct FaillockConfig {
+    dir: PathBuf,
+    deny: usize,
+    fail_interval: u64,
+    /// The seconds after which an account is unlocked again. 0 means never.
+    unlock_time: u64,
+    even_deny_root: bool,
+    root_unlock_time: u64,
+}
+
+impl Default for FaillockConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("/var/run/faillock"),
+            deny: 3,
+            fail_interval: 900,
+            unlock_time: 600,
+            even_deny_root: false,
+            root_unlock_time: 600,
+        }
+    }
+}
+
+impl FaillockConfig {
+    fn parse(contents: &str) -> Self {
+        let mut config = Self::default();
+        let mut root_unlock_time = None;
+
+        for line in contents.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => (line, ""),
+            };
+
+            let seconds = |value: &str| match value {
+                "never" => Some(0),
+                value => value.parse().ok(),
+            };
+
+            match key {
+                "dir" if !value.is_empty() => config.dir = PathBuf::from(value),
+                "deny" => config.deny = value.parse().unwrap_or(config.deny),
+                "fail_interval" => {
+                    config.fail_interval = value.parse().unwrap_or(config.fail_interval)
+                }
+                "unlock_time" => config.unlock_time = seconds(value).unwrap_or(config.unlock_time),
+                "root_unlock_time" => root_unlock_time = seconds(value),
+                "even_deny_root" => config.even_deny_root = true,
+                _ => {}
+            }
+        }
+
+        config.root_unlock_time = root_unlock_time.unwrap_or(config.unlock_time);
+        config
+    }
+
+    /// Whether the failures in a tally file lock the account in the same way `pam_faillock`
+    /// decides it
+    fn is_locked(&self, tallies: &[u8], is_root: bool, now: u64) -> bool {
+        if self.deny == 0 || (is_root && !self.even_deny_root) {
+            return false;
+        }
+
+        let failures: Vec<u64> = tallies
+            .chunks_exact(TALLY_SIZE)
+            .filter(|record| {
+                let status = &record[TALLY_STATUS_OFFSET..TALLY_TIME_OFFSET];
+                u16::from_ne_bytes([status[0], status[1]]) & TALLY_STATUS_VALID != 0
+            })
+            .map(|record| {
+                let mut time = [0; 8];
+                time.copy_from_slice(&record[TALLY_TIME_OFFSET..TALLY_SIZE]);
+                u64::from_ne_bytes(time)
+            })
+            .collect();
+
+        let Some(latest) = failures.iter().copied().max() else {
+            return false;
+        };
+
+        let recent_failures = failures
+            .iter()
+            .filter(|time| latest - **time < self.fail_interval)
+            .count();
+        if recent_failures < self.deny {
+            return false;
+        }
+
+        let unlock_time = if is_root {
+            self.root_unlock_time
+        } else {
+            self.unlock_time
+        };
+
+        unlock_time == 0 || latest.saturating_add(unlock_time) >= now
+    }
+}
+
+/// Whether `pam_faillock` has locked the account of a user
+///
+/// `pam_faillock` fails the authentication with `PAM_AUTH_ERR` whilst an account is locked, which
+/// is the same as for a wrong password. Its messages are translated and can be silenced, so the
+/// lockout is detected from the failures it records instead.
+pub fn is_locked(config_path: impl AsRef<Path>, username: &str, is_root: bool, now: u64) -> bool {
+    // The username is typed in at the login form and becomes a path within the tally directory
+    if username.contains('/')
+        || username == "."
+        || username == ".."
+        || check_username(username).is_err()
+    {
+        return false;
+    }
+
+    let config = FaillockConfig::parse(&fs::read_to_string(config_path).unwrap_or_default());
+
+    match fs::read(config.dir.join(username)) {
+        Ok(tallies) => config.is_locked(&tallies, is_root, now),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tally(time: u64, status: u16) -> Vec<u8> {
+        let mut record = vec![0; TALLY_SIZE];
+        record[..4].copy_from_slice(b"tty2");
+        record[TALLY_STATUS_OFFSET..TALLY_TIME_OFFSET].copy_from_slice(&status.to_ne_bytes());
+        record[TALLY_TIME_OFFSET..].copy_from_slice(&time.to_ne_bytes());
+        record
+    }
+
+    #[test]
+    fn faillock_state() {
+        let config = FaillockConfig::parse(
+            "# Lock after 2 failures\ndir = /run/faillock\ndeny = 2\nunlock_time = 60\nsilent\n",
+        );
+        assert_eq!(config.dir, PathBuf::from("/run/faillock"));
+        assert_eq!(config.deny, 2);
+        assert_eq!(config.unlock_time, 60);
+        assert_eq!(config.root_unlock_time, 60);
+        assert!(!config.even_deny_root);
+
+        let tallies = [tally(1000, 1), tally(1010, 1)].concat();
+        assert!(config.is_locked(&tallies, false, 1020));
+        // The lock is over after the unlock time
+        assert!(!config.is_locked(&tallies, false, 1100));
+        // Root is not locked unless `even_deny_root` is set
+        assert!(!config.is_locked(&tallies, true, 1020));
+
+        // Records that are not valid anymore (e.g. after `faillock --reset`) do not count
+        let tallies = [tally(1000, 0), tally(1010, 1)].concat();
+        assert!(!config.is_locked(&tallies, false, 1020));
+
+        // Failures that are too far apart do not count together
+        let tallies = [tally(0, 1), tally(1000, 1)].concat();
+        assert!(!config.is_locked(&tallies, false, 1020));
+
+        let config = FaillockConfig::parse("deny = 1\nunlock_time = never\n");
+        assert!(config.is_locked(&tally(0, 1), false, u32::MAX.into()));
+    }
+
+    #[test]
+    fn username_outside_of_tally_dir() {
+        let dir = std::env::temp_dir().join(format!("lemurs-faillock-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("tally")).unwrap();
+
+        let config_path = dir.join("faillock.conf");
+        fs::write(
+            &config_path,
+            format!("dir = {}\ndeny = 1\n", dir.join("tally").display()),
+        )
+        .unwrap();
+        fs::write(dir.join("tally/lemur"), tally(1000, 1)).unwrap();
+        fs::write(dir.join("other"), tally(1000, 1)).unwrap();
+
+        assert!(is_locked(&config_path, "lemur", false, 1010));
+        assert!(!is_locked(&config_path, "../other", false, 1010));
+        assert!(!is_locked(&config_path, "..", false, 1010));
+        assert!(!is_locked(&config_path, "Not A Username", false, 1010));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,48 +1,73 @@
+mod faillock;
 mod pam;
+pub mod throttle;
 pub mod utmpx;
 
-use ::pam::{Authenticator, PasswordConv};
 use log::info;
 
-use crate::auth::pam::open_session;
-pub use crate::auth::pam::AuthenticationError;
+use crate::auth::pam::{open_session, PamSession, SessionInfo};
+pub use crate::auth::pam::{AuthenticationError, Conversation};
+use crate::config::Config;
 
 pub struct AuthUserInfo<'a> {
     // This is used to keep the user session. If the struct is dropped then the user session is
     // also automatically dropped.
     #[allow(dead_code)]
-    authenticator: Authenticator<'a, PasswordConv>,
+    session: PamSession<'a>,
 
     pub name: String,
     pub uid: u32,
     pub gid: u32,
+    #[allow(dead_code)]
     pub gecos: String,
     pub dir: String,
     pub shell: String,
+
+    /// The environment variables that were set by the PAM modules (e.g. `XDG_SESSION_ID` and
+    /// `XDG_RUNTIME_DIR` from `pam_systemd`)
+    pub environment: Vec<(String, String)>,
 }
 
 pub fn try_auth<'a>(
     username: String,
     password: String,
+    session_type: &str,
+    config: &Config,
+    conversation: Box<dyn Conversation + 'a>,
 ) -> Result<AuthUserInfo<'a>, AuthenticationError> {
     info!("Login attempt for '{}'", username);
 
-    open_session(username.clone(), password)
-        .map(|(authenticator, entry)| AuthUserInfo {
-            authenticator,
+    let session_info = SessionInfo {
+        session_type,
+        tty: config.tty,
+    };
+
+    open_session(
+        username.clone(),
+        password,
+        &config.pam_service,
+        session_info,
+        conversation,
+    )
+    .map(|(mut session, entry)| {
+        let environment = session.environment();
+        info!(
+            "Gotten {} environment variables from PAM",
+            environment.len()
+        );
+
+        AuthUserInfo {
+            session,
             name: entry.name,
             uid: entry.uid,
             gid: entry.gid,
             gecos: entry.gecos,
             dir: entry.dir,
             shell: entry.shell,
-        })
-        .map_err(|err| {
-            info!(
-                "Authentication failed for '{}'. Reason: {}",
-                username,
-                err.to_string()
-            );
-            err
-        })
+            environment,
+        }
+    })
+    .inspect_err(|err| {
+        info!("Authentication failed for '{}'. Reason: {}", username, err);
+    })
 }
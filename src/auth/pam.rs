@@ -1,69 +1,859 @@
-use log::info;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::mem;
+use std::ptr;
 
-use pam::{Authenticator, PasswordConv};
+use libc::{c_int, c_void};
+use log::{info, warn};
 
-const PAM_SERVICE: &str = "system-login";
+use pam_sys::{
+    PamConversation, PamFlag, PamHandle, PamItemType, PamMessage, PamMessageStyle, PamResponse,
+    PamReturnCode,
+};
 
 use pgs_files::passwd::{get_entry_by_name, PasswdEntry};
 
+use super::faillock::{self, FAILLOCK_CONF_PATH};
+use super::throttle;
+
 /// All the different errors that can occur during PAM opening an authenticated session
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub enum AuthenticationError {
-    PamService,
+    /// The PAM service with this name could not be started or is misconfigured
+    PamService(String),
     AccountValidation,
+    /// The account has expired
+    AccountExpired,
+    /// The account is locked (e.g. by `pam_faillock` after too many failed attempts)
+    AccountLocked,
+    /// Access to the account is denied by a rule (e.g. of `pam_access`, `pam_time` or
+    /// `pam_nologin`)
+    AccessDenied,
     UsernameNotFound,
     SessionOpen,
+    /// A prompt of PAM (e.g. for a one-time code) was cancelled
+    Cancelled,
+    /// PAM failed for another reason than the credentials (e.g. an unreachable server)
+    Failed(PamReturnCode),
+    /// The password has expired and changing it was cancelled
+    PasswordChangeCancelled,
+    /// The new password was not accepted (e.g. because it is too weak)
+    PasswordChangeRejected,
+    /// The current password could not be verified whilst changing it
+    PasswordChangeVerification,
+    /// Changing the expired password failed for another reason
+    PasswordChange,
 }
 
-impl ToString for AuthenticationError {
-    fn to_string(&self) -> String {
+impl AuthenticationError {
+    /// The error for a failed `pam_authenticate` or `pam_acct_mgmt`
+    fn from_code(code: PamReturnCode, pam_service: &str) -> Self {
+        match code {
+            PamReturnCode::ACCT_EXPIRED => AuthenticationError::AccountExpired,
+            PamReturnCode::MAXTRIES => AuthenticationError::AccountLocked,
+            PamReturnCode::PERM_DENIED => AuthenticationError::AccessDenied,
+            PamReturnCode::USER_UNKNOWN => AuthenticationError::UsernameNotFound,
+            PamReturnCode::SERVICE_ERR
+            | PamReturnCode::SYSTEM_ERR
+            | PamReturnCode::OPEN_ERR
+            | PamReturnCode::SYMBOL_ERR
+            | PamReturnCode::MODULE_UNKNOWN => {
+                AuthenticationError::PamService(pam_service.to_string())
+            }
+            PamReturnCode::AUTH_ERR => AuthenticationError::AccountValidation,
+            code => AuthenticationError::Failed(code),
+        }
+    }
+
+    /// Whether the error is caused by wrong credentials, which counts as a failed login attempt
+    pub fn is_credential_failure(&self) -> bool {
+        matches!(
+            self,
+            AuthenticationError::AccountValidation
+                | AuthenticationError::UsernameNotFound
+                | AuthenticationError::AccountLocked
+        )
+    }
+}
+
+impl fmt::Display for AuthenticationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            AuthenticationError::PamService => format!("Failed to create authenticator with PAM service '{}'", PAM_SERVICE),
-            AuthenticationError::AccountValidation => "Invalid login credentials".to_string(),
-            AuthenticationError::UsernameNotFound => "Login creditionals are valid, but username is not found. This should not be possible :(".to_string(),
-            AuthenticationError::SessionOpen => "Failed to open a PAM session".to_string(),
+            AuthenticationError::PamService(service) => write!(
+                f,
+                "Failed to create authenticator with PAM service '{}'",
+                service
+            ),
+            AuthenticationError::AccountValidation => write!(f, "Invalid login credentials"),
+            AuthenticationError::AccountExpired => write!(f, "The account has expired"),
+            AuthenticationError::AccountLocked => write!(f, "The account is locked"),
+            AuthenticationError::AccessDenied => write!(f, "Access to the account is denied"),
+            AuthenticationError::UsernameNotFound => write!(f, "The user is not known"),
+            AuthenticationError::SessionOpen => write!(f, "Failed to open a PAM session"),
+            AuthenticationError::Cancelled => write!(f, "The authentication was cancelled"),
+            AuthenticationError::Failed(code) => write!(f, "Authentication failed. Code: {}", code),
+            AuthenticationError::PasswordChangeCancelled => {
+                write!(f, "Changing the expired password was cancelled")
+            }
+            AuthenticationError::PasswordChangeRejected => {
+                write!(f, "The new password was rejected")
+            }
+            AuthenticationError::PasswordChangeVerification => write!(
+                f,
+                "Failed to verify the current password whilst changing it"
+            ),
+            AuthenticationError::PasswordChange => {
+                write!(f, "Failed to change the expired password")
+            }
+        }
+    }
+}
+
+/// The information about the session that is registered with PAM before it is opened
+///
+/// This is picked up by modules such as `pam_systemd` to register a proper session with logind.
+pub struct SessionInfo<'b> {
+    /// The value for `XDG_SESSION_TYPE` (e.g. `x11`, `wayland` or `tty`)
+    pub session_type: &'b str,
+    pub tty: u8,
+}
+
+/// A conversation with PAM
+///
+/// PAM modules can send any number of prompts and messages during authentication (e.g. for
+/// one-time passwords, smartcards or fingerprint readers). These are passed on to this.
+pub trait Conversation {
+    /// PAM requests a value which can be shown whilst it is typed
+    fn prompt_echo(&mut self, msg: &str) -> Result<String, ()>;
+    /// PAM requests a secret value which should not be shown whilst it is typed
+    fn prompt_blind(&mut self, msg: &str) -> Result<String, ()>;
+    /// An informational message from PAM
+    fn info(&mut self, msg: &str);
+    /// An error message from PAM
+    fn error(&mut self, msg: &str);
+    /// The password has expired and a new one is requested
+    ///
+    /// The new password should already be confirmed when it is returned.
+    fn new_password(&mut self) -> Result<String, ()>;
+}
+
+/// Answers the first secret prompt with the password from the login form and passes all other
+/// prompts and messages on to another conversation
+///
+/// Whilst an expired password is changed, the first secret prompt is for the current password and
+/// answered with the password from the login form. The prompts after it are for the new password
+/// and answered with a password requested from the other conversation. The prompts are told apart
+/// by their order, because their text differs per module and language.
+struct LoginConversation<'a> {
+    password: String,
+    /// Whether the password from the login form has already been given to PAM
+    password_used: bool,
+    /// Whether an expired password is being changed
+    changing_password: bool,
+    /// Whether the current password has already been given whilst changing the password
+    current_password_given: bool,
+    new_password: Option<String>,
+    /// Whether the other conversation cancelled giving a new password
+    new_password_cancelled: bool,
+    /// Whether the other conversation cancelled a prompt
+    prompt_cancelled: bool,
+    inner: Box<dyn Conversation + 'a>,
+}
+
+impl<'a> LoginConversation<'a> {
+    fn new(password: String, inner: Box<dyn Conversation + 'a>) -> Self {
+        Self {
+            password,
+            password_used: false,
+            changing_password: false,
+            current_password_given: false,
+            new_password: None,
+            new_password_cancelled: false,
+            prompt_cancelled: false,
+            inner,
+        }
+    }
+}
+
+impl<'a> Conversation for LoginConversation<'a> {
+    fn prompt_echo(&mut self, msg: &str) -> Result<String, ()> {
+        self.inner.prompt_echo(msg).inspect_err(|_| {
+            self.prompt_cancelled = true;
+        })
+    }
+    fn prompt_blind(&mut self, msg: &str) -> Result<String, ()> {
+        if self.changing_password {
+            if !self.current_password_given {
+                self.current_password_given = true;
+                return Ok(self.password.clone());
+            }
+
+            if let Some(new_password) = &self.new_password {
+                return Ok(new_password.clone());
+            }
+
+            let new_password = self.new_password().inspect_err(|_| {
+                self.new_password_cancelled = true;
+            })?;
+            self.new_password = Some(new_password.clone());
+            return Ok(new_password);
+        }
+
+        if self.password_used {
+            self.inner.prompt_blind(msg).inspect_err(|_| {
+                self.prompt_cancelled = true;
+            })
+        } else {
+            self.password_used = true;
+            Ok(self.password.clone())
+        }
+    }
+    fn info(&mut self, msg: &str) {
+        info!("PAM info: {}", msg);
+        self.inner.info(msg)
+    }
+    fn error(&mut self, msg: &str) {
+        warn!("PAM error: {}", msg);
+
+        // The new password was rejected, so a different one has to be requested when PAM asks
+        // again
+        if self.changing_password {
+            self.new_password = None;
+        }
+
+        self.inner.error(msg)
+    }
+    fn new_password(&mut self) -> Result<String, ()> {
+        self.inner.new_password()
+    }
+}
+
+/// A PAM transaction. This closes the PAM session, if it was opened, when dropped.
+pub struct PamSession<'a> {
+    handle: &'a mut PamHandle,
+    // NOTE: This is accessed by PAM through the `data_ptr` of the conversation. It is boxed so
+    // that the pointer stays valid when the session moves.
+    conversation: Box<LoginConversation<'a>>,
+    /// Whether the credentials of the user were established and have to be deleted again
+    has_credentials: bool,
+    has_open_session: bool,
+    /// The status of the last call to PAM, which is passed on to `pam_end`
+    last_status: PamReturnCode,
+}
+
+impl<'a> PamSession<'a> {
+    fn start(
+        service: &str,
+        username: &str,
+        conversation: LoginConversation<'a>,
+    ) -> Result<Self, PamReturnCode> {
+        let mut conversation = Box::new(conversation);
+        let pam_conversation = PamConversation {
+            conv: Some(converse::<LoginConversation<'a>>),
+            data_ptr: &mut *conversation as *mut LoginConversation<'a> as *mut c_void,
+        };
+
+        let mut handle: *mut PamHandle = ptr::null_mut();
+        match pam_sys::start(service, Some(username), &pam_conversation, &mut handle) {
+            PamReturnCode::SUCCESS if !handle.is_null() => Ok(PamSession {
+                // SAFETY: PAM gave us a valid handle which stays valid until `pam_end`, which is
+                // only called on drop.
+                handle: unsafe { &mut *handle },
+                conversation,
+                has_credentials: false,
+                has_open_session: false,
+                last_status: PamReturnCode::SUCCESS,
+            }),
+            PamReturnCode::SUCCESS => Err(PamReturnCode::SYSTEM_ERR),
+            code => Err(code),
+        }
+    }
+
+    /// Remember the status of a call to PAM and turn it into a `Result`
+    fn check(&mut self, code: PamReturnCode) -> Result<(), PamReturnCode> {
+        self.last_status = code;
+
+        match code {
+            PamReturnCode::SUCCESS => Ok(()),
+            code => Err(code),
         }
     }
+
+    fn authenticate(&mut self) -> Result<(), PamReturnCode> {
+        let code = pam_sys::authenticate(self.handle, PamFlag::NONE);
+        self.check(code)
+    }
+
+    fn acct_mgmt(&mut self) -> Result<(), PamReturnCode> {
+        let code = pam_sys::acct_mgmt(self.handle, PamFlag::NONE);
+        self.check(code)
+    }
+
+    /// Change the expired password of the user
+    fn change_expired_password(&mut self) -> Result<(), AuthenticationError> {
+        self.conversation.changing_password = true;
+        self.conversation.current_password_given = false;
+        let code = pam_sys::chauthtok(self.handle, PamFlag::CHANGE_EXPIRED_AUTHTOK);
+        self.conversation.changing_password = false;
+        self.conversation.new_password = None;
+
+        if self.conversation.new_password_cancelled {
+            return Err(AuthenticationError::PasswordChangeCancelled);
+        }
+
+        self.check(code).map_err(|code| {
+            warn!("Failed to change the expired password. Code: {}", code);
+
+            match code {
+                PamReturnCode::AUTHTOK_ERR => AuthenticationError::PasswordChangeRejected,
+                PamReturnCode::AUTHTOK_RECOVERY_ERR => {
+                    AuthenticationError::PasswordChangeVerification
+                }
+                _ => AuthenticationError::PasswordChange,
+            }
+        })
+    }
+
+    fn setcred(&mut self, flag: PamFlag) -> Result<(), PamReturnCode> {
+        let code = pam_sys::setcred(self.handle, flag);
+        self.check(code)
+    }
+
+    fn putenv(&mut self, key: &str, value: &str) -> Result<(), PamReturnCode> {
+        let code = pam_sys::putenv(self.handle, &format!("{key}={value}"));
+        self.check(code)
+    }
+
+    fn set_tty(&mut self, tty: &str) -> Result<(), PamReturnCode> {
+        let tty = CString::new(tty).map_err(|_| PamReturnCode::BUF_ERR)?;
+
+        // SAFETY: PAM copies the string, so it only has to live for the duration of this call.
+        let code = pam_sys::set_item(self.handle, PamItemType::TTY, unsafe {
+            &*(tty.as_ptr() as *const c_void)
+        });
+        self.check(code)
+    }
+
+    fn open_session(&mut self) -> Result<(), PamReturnCode> {
+        self.setcred(PamFlag::ESTABLISH_CRED)?;
+        self.has_credentials = true;
+        let code = pam_sys::open_session(self.handle, PamFlag::NONE);
+        self.check(code)?;
+        self.has_open_session = true;
+
+        // Follow OpenSSH and call pam_setcred before and after open_session
+        self.setcred(PamFlag::REINITIALIZE_CRED)
+    }
+
+    /// Fetch the environment variables that the PAM modules have set for the session
+    pub fn environment(&mut self) -> Vec<(String, String)> {
+        let env_list = pam_sys::getenvlist(self.handle);
+
+        if env_list.is_null() {
+            return Vec::new();
+        }
+
+        let mut environment = Vec::new();
+
+        // SAFETY: PAM returns a NULL-terminated list of NUL-terminated strings that are all
+        // allocated with malloc and now owned by us.
+        unsafe {
+            let mut i = 0;
+            loop {
+                let entry = *env_list.offset(i);
+                if entry.is_null() {
+                    break;
+                }
+
+                if let Some((key, value)) = CStr::from_ptr(entry).to_string_lossy().split_once('=')
+                {
+                    environment.push((key.to_string(), value.to_string()));
+                }
+
+                libc::free(entry as *mut c_void);
+                i += 1;
+            }
+
+            libc::free(env_list as *mut c_void);
+        }
+
+        environment
+    }
+}
+
+impl<'a> Drop for PamSession<'a> {
+    fn drop(&mut self) {
+        if self.has_open_session {
+            self.last_status = pam_sys::close_session(self.handle, PamFlag::NONE);
+        }
+
+        if self.has_credentials {
+            self.last_status = pam_sys::setcred(self.handle, PamFlag::DELETE_CRED);
+        }
+
+        pam_sys::end(self.handle, self.last_status);
+    }
+}
+
+/// The conversation function that is called by PAM
+extern "C" fn converse<C: Conversation>(
+    num_msg: c_int,
+    msg: *mut *mut PamMessage,
+    out_resp: *mut *mut PamResponse,
+    appdata_ptr: *mut c_void,
+) -> c_int {
+    if num_msg <= 0 || msg.is_null() || out_resp.is_null() || appdata_ptr.is_null() {
+        return PamReturnCode::CONV_ERR as c_int;
+    }
+
+    // NOTE: The responses are freed by PAM, so they have to be allocated with malloc
+    let responses = unsafe {
+        libc::calloc(num_msg as usize, mem::size_of::<PamResponse>()) as *mut PamResponse
+    };
+    if responses.is_null() {
+        return PamReturnCode::BUF_ERR as c_int;
+    }
+
+    // SAFETY: The `data_ptr` is always set to a `C` by `PamSession::start`
+    let conversation = unsafe { &mut *(appdata_ptr as *mut C) };
+
+    for i in 0..num_msg as isize {
+        let (style, text) = unsafe {
+            let message = &**msg.offset(i);
+            let text = if message.msg.is_null() {
+                Default::default()
+            } else {
+                CStr::from_ptr(message.msg).to_string_lossy()
+            };
+
+            (PamMessageStyle::from(message.msg_style), text)
+        };
+
+        let response = match style {
+            PamMessageStyle::PROMPT_ECHO_ON => Some(conversation.prompt_echo(&text)),
+            PamMessageStyle::PROMPT_ECHO_OFF => Some(conversation.prompt_blind(&text)),
+            PamMessageStyle::TEXT_INFO => {
+                conversation.info(&text);
+                None
+            }
+            PamMessageStyle::ERROR_MSG => {
+                conversation.error(&text);
+                None
+            }
+        };
+
+        if let Some(response) = response {
+            let Some(response) = response.ok().and_then(|r| CString::new(r).ok()) else {
+                free_responses(responses, num_msg);
+                return PamReturnCode::CONV_ERR as c_int;
+            };
+
+            unsafe { (*responses.offset(i)).resp = libc::strdup(response.as_ptr()) };
+        }
+    }
+
+    unsafe { *out_resp = responses };
+
+    PamReturnCode::SUCCESS as c_int
+}
+
+fn free_responses(responses: *mut PamResponse, num_msg: c_int) {
+    for i in 0..num_msg as isize {
+        unsafe {
+            let response = (*responses.offset(i)).resp;
+            if !response.is_null() {
+                libc::free(response as *mut c_void);
+            }
+        }
+    }
+
+    unsafe { libc::free(responses as *mut c_void) };
 }
 
 /// Open a PAM authenticated session
+///
+/// The first secret prompt is answered with the `password`. All other prompts and messages are
+/// passed on to the `conversation`.
 pub fn open_session<'a>(
     username: impl ToString,
     password: impl ToString,
-) -> Result<(Authenticator<'a, PasswordConv>, PasswdEntry), AuthenticationError> {
+    pam_service: &str,
+    session_info: SessionInfo,
+    conversation: Box<dyn Conversation + 'a>,
+) -> Result<(PamSession<'a>, PasswdEntry), AuthenticationError> {
     let username = username.to_string();
     let password = password.to_string();
 
     info!("Started opening session");
 
-    let mut authenticator =
-        Authenticator::with_password(PAM_SERVICE).map_err(|_| AuthenticationError::PamService)?;
+    let mut session = PamSession::start(
+        pam_service,
+        &username,
+        LoginConversation::new(password, conversation),
+    )
+    .map_err(|code| {
+        warn!(
+            "Failed to start PAM service '{}'. Code: {}",
+            pam_service, code
+        );
+        AuthenticationError::PamService(pam_service.to_string())
+    })?;
 
     info!("Gotten Authenticator");
 
-    // Authenticate the user
-    authenticator
-        .get_handler()
-        .set_credentials(&username, &password);
+    // Validate the account
+    session.authenticate().map_err(|code| {
+        warn!("PAM authentication failed. Code: {}", code);
 
-    info!("Got handler");
+        // Modules report a cancelled prompt in different ways (e.g. as `PAM_AUTH_ERR`)
+        if session.conversation.prompt_cancelled {
+            return AuthenticationError::Cancelled;
+        }
 
-    // Validate the account
-    authenticator
-        .authenticate()
-        .map_err(|_| AuthenticationError::AccountValidation)?;
+        let is_root = get_entry_by_name(&username).is_some_and(|entry| entry.uid == 0);
+        if code == PamReturnCode::AUTH_ERR
+            && faillock::is_locked(FAILLOCK_CONF_PATH, &username, is_root, throttle::now())
+        {
+            info!("The account is locked by pam_faillock");
+            return AuthenticationError::AccountLocked;
+        }
+
+        AuthenticationError::from_code(code, pam_service)
+    })?;
+
+    match session.acct_mgmt() {
+        Ok(()) => {}
+        Err(PamReturnCode::NEW_AUTHTOK_REQD) => {
+            info!("Password has expired and has to be changed");
+            session.change_expired_password()?;
+            info!("Changed expired password");
+        }
+        Err(code) => {
+            warn!("PAM account validation failed. Code: {}", code);
+            if session.conversation.prompt_cancelled {
+                return Err(AuthenticationError::Cancelled);
+            }
+            return Err(AuthenticationError::from_code(code, pam_service));
+        }
+    }
 
     info!("Validated account");
 
     // NOTE: Maybe we should also load all groups here
     let passwd_entry = get_entry_by_name(&username).ok_or(AuthenticationError::UsernameNotFound)?;
 
-    authenticator
-        .open_session()
-        .map_err(|_| AuthenticationError::SessionOpen)?;
+    // Register the session with the modules that open it (e.g. pam_systemd)
+    let tty = session_info.tty.to_string();
+    for (key, value) in [
+        ("XDG_SESSION_TYPE", session_info.session_type),
+        ("XDG_SESSION_CLASS", "user"),
+        ("XDG_SEAT", "seat0"),
+        ("XDG_VTNR", &tty),
+    ] {
+        if let Err(code) = session.putenv(key, value) {
+            warn!(
+                "Failed to put '{}' in the PAM environment. Code: {}",
+                key, code
+            );
+        }
+    }
+    if let Err(code) = session.set_tty(&format!("/dev/tty{tty}")) {
+        warn!("Failed to set the PAM tty. Code: {}", code);
+    }
+
+    session.open_session().map_err(|code| {
+        warn!("Failed to open PAM session. Code: {}", code);
+        AuthenticationError::SessionOpen
+    })?;
 
     info!("Opened session");
 
     // NOTE: Logout happens automatically here with `drop` of session and context
-    Ok((authenticator, passwd_entry))
+    Ok((session, passwd_entry))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records everything it receives and answers prompts from a fixed list
+    #[derive(Default)]
+    struct MockConversation {
+        answers: Vec<&'static str>,
+        received: Vec<String>,
+    }
+
+    impl MockConversation {
+        fn answer(&mut self) -> Result<String, ()> {
+            if self.answers.is_empty() {
+                Err(())
+            } else {
+                Ok(self.answers.remove(0).to_string())
+            }
+        }
+    }
+
+    impl<T: Conversation + ?Sized> Conversation for &mut T {
+        fn prompt_echo(&mut self, msg: &str) -> Result<String, ()> {
+            (**self).prompt_echo(msg)
+        }
+        fn prompt_blind(&mut self, msg: &str) -> Result<String, ()> {
+            (**self).prompt_blind(msg)
+        }
+        fn info(&mut self, msg: &str) {
+            (**self).info(msg)
+        }
+        fn error(&mut self, msg: &str) {
+            (**self).error(msg)
+        }
+        fn new_password(&mut self) -> Result<String, ()> {
+            (**self).new_password()
+        }
+    }
+
+    impl Conversation for MockConversation {
+        fn prompt_echo(&mut self, msg: &str) -> Result<String, ()> {
+            self.received.push(format!("echo:{msg}"));
+            self.answer()
+        }
+        fn prompt_blind(&mut self, msg: &str) -> Result<String, ()> {
+            self.received.push(format!("blind:{msg}"));
+            self.answer()
+        }
+        fn info(&mut self, msg: &str) {
+            self.received.push(format!("info:{msg}"));
+        }
+        fn error(&mut self, msg: &str) {
+            self.received.push(format!("error:{msg}"));
+        }
+        fn new_password(&mut self) -> Result<String, ()> {
+            self.received.push("new_password".to_string());
+            self.answer()
+        }
+    }
+
+    /// Runs the PAM conversation function with the given messages in the same way PAM would
+    fn run_conversation<C: Conversation>(
+        conversation: &mut C,
+        messages: &[(PamMessageStyle, &str)],
+    ) -> Result<Vec<Option<String>>, PamReturnCode> {
+        let texts: Vec<CString> = messages
+            .iter()
+            .map(|(_, text)| CString::new(*text).unwrap())
+            .collect();
+        let mut messages: Vec<PamMessage> = messages
+            .iter()
+            .zip(texts.iter())
+            .map(|((style, _), text)| PamMessage {
+                msg_style: *style as c_int,
+                msg: text.as_ptr(),
+            })
+            .collect();
+        let mut message_ptrs: Vec<*mut PamMessage> =
+            messages.iter_mut().map(|m| m as *mut PamMessage).collect();
+
+        let mut responses: *mut PamResponse = ptr::null_mut();
+        let code = converse::<C>(
+            message_ptrs.len() as c_int,
+            message_ptrs.as_mut_ptr(),
+            &mut responses,
+            conversation as *mut C as *mut c_void,
+        );
+
+        if code != PamReturnCode::SUCCESS as c_int {
+            return Err(PamReturnCode::from(code));
+        }
+
+        let answers = (0..message_ptrs.len() as isize)
+            .map(|i| unsafe {
+                let response = (*responses.offset(i)).resp;
+                if response.is_null() {
+                    None
+                } else {
+                    Some(CStr::from_ptr(response).to_string_lossy().to_string())
+                }
+            })
+            .collect();
+        free_responses(responses, message_ptrs.len() as c_int);
+
+        Ok(answers)
+    }
+
+    #[test]
+    fn login_conversation() {
+        let mut mock = MockConversation {
+            answers: vec!["123456", "yes"],
+            ..Default::default()
+        };
+
+        {
+            let mut conversation =
+                LoginConversation::new("hunter2".to_string(), Box::new(&mut mock));
+
+            // The first secret prompt is answered with the password of the login form
+            assert_eq!(
+                run_conversation(
+                    &mut conversation,
+                    &[(PamMessageStyle::PROMPT_ECHO_OFF, "Password: ")]
+                ),
+                Ok(vec![Some("hunter2".to_string())])
+            );
+
+            // All following prompts are passed on
+            assert_eq!(
+                run_conversation(
+                    &mut conversation,
+                    &[
+                        (PamMessageStyle::TEXT_INFO, "Touch your key"),
+                        (PamMessageStyle::PROMPT_ECHO_OFF, "Verification code: "),
+                        (PamMessageStyle::ERROR_MSG, "Almost expired"),
+                        (PamMessageStyle::PROMPT_ECHO_ON, "Continue? "),
+                    ]
+                ),
+                Ok(vec![
+                    None,
+                    Some("123456".to_string()),
+                    None,
+                    Some("yes".to_string())
+                ])
+            );
+
+            assert!(!conversation.prompt_cancelled);
+
+            // A failing prompt fails the whole conversation
+            assert_eq!(
+                run_conversation(
+                    &mut conversation,
+                    &[(PamMessageStyle::PROMPT_ECHO_OFF, "PIN: ")]
+                ),
+                Err(PamReturnCode::CONV_ERR)
+            );
+            assert!(conversation.prompt_cancelled);
+        }
+
+        assert_eq!(
+            mock.received,
+            vec![
+                "info:Touch your key",
+                "blind:Verification code: ",
+                "error:Almost expired",
+                "echo:Continue? ",
+                "blind:PIN: ",
+            ]
+        );
+    }
+
+    #[test]
+    fn expired_password_conversation() {
+        let mut mock = MockConversation {
+            answers: vec!["weak", "correct horse"],
+            ..Default::default()
+        };
+
+        {
+            let mut conversation =
+                LoginConversation::new("hunter2".to_string(), Box::new(&mut mock));
+            conversation.password_used = true;
+            conversation.changing_password = true;
+
+            // The current password is the one from the login form and the new password is
+            // requested once for both the prompt and the retype. This does not depend on the
+            // language of the prompts.
+            assert_eq!(
+                run_conversation(
+                    &mut conversation,
+                    &[
+                        (PamMessageStyle::PROMPT_ECHO_OFF, "Mot de passe actuel : "),
+                        (PamMessageStyle::PROMPT_ECHO_OFF, "Nouveau mot de passe : "),
+                        (
+                            PamMessageStyle::PROMPT_ECHO_OFF,
+                            "Retapez le nouveau mot de passe : "
+                        ),
+                    ]
+                ),
+                Ok(vec![
+                    Some("hunter2".to_string()),
+                    Some("weak".to_string()),
+                    Some("weak".to_string()),
+                ])
+            );
+
+            // A rejected password causes a new one to be requested
+            assert_eq!(
+                run_conversation(
+                    &mut conversation,
+                    &[
+                        (PamMessageStyle::ERROR_MSG, "BAD PASSWORD: too short"),
+                        (PamMessageStyle::PROMPT_ECHO_OFF, "New password: "),
+                    ]
+                ),
+                Ok(vec![None, Some("correct horse".to_string())])
+            );
+            assert!(!conversation.new_password_cancelled);
+
+            // Cancelling the new password fails the conversation
+            conversation.new_password = None;
+            assert_eq!(
+                run_conversation(
+                    &mut conversation,
+                    &[(PamMessageStyle::PROMPT_ECHO_OFF, "New password: ")]
+                ),
+                Err(PamReturnCode::CONV_ERR)
+            );
+            assert!(conversation.new_password_cancelled);
+        }
+
+        assert_eq!(
+            mock.received,
+            vec![
+                "new_password",
+                "error:BAD PASSWORD: too short",
+                "new_password",
+                "new_password",
+            ]
+        );
+    }
+
+    #[test]
+    fn error_from_code() {
+        let error = |code| AuthenticationError::from_code(code, "lemurs");
+
+        assert!(matches!(
+            error(PamReturnCode::ACCT_EXPIRED),
+            AuthenticationError::AccountExpired
+        ));
+        assert!(matches!(
+            error(PamReturnCode::MAXTRIES),
+            AuthenticationError::AccountLocked
+        ));
+        assert!(matches!(
+            error(PamReturnCode::PERM_DENIED),
+            AuthenticationError::AccessDenied
+        ));
+        assert!(matches!(
+            error(PamReturnCode::USER_UNKNOWN),
+            AuthenticationError::UsernameNotFound
+        ));
+        assert!(matches!(
+            error(PamReturnCode::SERVICE_ERR),
+            AuthenticationError::PamService(service) if service == "lemurs"
+        ));
+        assert!(matches!(
+            error(PamReturnCode::AUTH_ERR),
+            AuthenticationError::AccountValidation
+        ));
+        assert!(matches!(
+            error(PamReturnCode::AUTHINFO_UNAVAIL),
+            AuthenticationError::Failed(PamReturnCode::AUTHINFO_UNAVAIL)
+        ));
+
+        // Only wrong credentials count as a failed login attempt
+        assert!(error(PamReturnCode::AUTH_ERR).is_credential_failure());
+        assert!(error(PamReturnCode::USER_UNKNOWN).is_credential_failure());
+        assert!(error(PamReturnCode::MAXTRIES).is_credential_failure());
+        assert!(!error(PamReturnCode::SERVICE_ERR).is_credential_failure());
+        assert!(!error(PamReturnCode::CONV_ERR).is_credential_failure());
+        assert!(!AuthenticationError::Cancelled.is_credential_failure());
+        assert!(!AuthenticationError::PasswordChangeCancelled.is_credential_failure());
+    }
 }
@@ -0,0 +1,270 @@
+use std::collections::BTreeMap;
+use std::fs::read_to_string;
+use std::path::Path;
+use std::time::SystemTime;
+
+use log::{info, warn};
+
+use crate::config::ThrottlingConfig;
+use crate::info_caching::write_atomically;
+
+/// The current time in seconds since the UNIX epoch
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FailedLogin {
+    failures: u32,
+    /// The time of the last failure in seconds since the UNIX epoch
+    last_failure: u64,
+}
+
+/// Logging in with a username is blocked because of previous failures
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoginBlock {
+    /// The time in seconds since the UNIX epoch at which logging in is allowed again
+    pub until: u64,
+    /// Whether the username is locked out instead of just delayed
+    pub locked_out: bool,
+}
+
+// Saved in the `failed_logins_path` file as one line per username
+// ```
+// HEX_USERNAME FAILURES LAST_FAILURE\n
+// ```
+/// The failed login attempts per username
+#[derive(Debug, Default)]
+pub struct FailedLogins(BTreeMap<String, FailedLogin>);
+
+impl FailedLogins {
+    fn parse(contents: &str) -> Self {
+        let mut failed_logins = BTreeMap::new();
+
+        for line in contents.lines() {
+            let mut fields = line.split_whitespace();
+
+            let entry = (|| {
+                let username = String::from_utf8(hex::decode(fields.next()?).ok()?).ok()?;
+                let failures = fields.next()?.parse().ok()?;
+                let last_failure = fields.next()?.parse().ok()?;
+
+                Some((
+                    username,
+                    FailedLogin {
+                        failures,
+                        last_failure,
+                    },
+                ))
+            })();
+
+            match entry {
+                Some((username, failed_login)) => {
+                    failed_logins.insert(username, failed_login);
+                }
+                None => warn!("Ignored invalid line in the failed logins file"),
+            }
+        }
+
+        Self(failed_logins)
+    }
+
+    fn serialize(&self) -> String {
+        self.0
+            .iter()
+            .map(|(username, failed_login)| {
+                format!(
+                    "{} {} {}\n",
+                    hex::encode(username),
+                    failed_login.failures,
+                    failed_login.last_failure
+                )
+            })
+            .collect()
+    }
+
+    pub fn load(path: &str) -> Self {
+        match read_to_string(path) {
+            Ok(contents) => Self::parse(&contents),
+            Err(err) => {
+                info!("No failed logins loaded from '{}'. Reason: '{}'", path, err);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self, path: &str) {
+        if let Err(err) = write_atomically(Path::new(path), &self.serialize()) {
+            warn!(
+                "Failed to save failed logins to '{}'. Reason: '{}'",
+                path, err
+            );
+        }
+    }
+
+    /// Whether the failures have been forgotten
+    fn is_expired(failed_login: &FailedLogin, config: &ThrottlingConfig, now: u64) -> bool {
+        let since_failure = now.saturating_sub(failed_login.last_failure);
+
+        since_failure >= config.reset_after
+            || (Self::is_locked_out(failed_login, config)
+                && since_failure >= config.lockout_duration)
+    }
+
+    fn is_locked_out(failed_login: &FailedLogin, config: &ThrottlingConfig) -> bool {
+        config.lockout_attempts != 0 && failed_login.failures >= config.lockout_attempts
+    }
+
+    /// Whether logging in with a username is currently blocked
+    ///
+    /// After every failure the login is delayed, starting at `delay` seconds and doubling with
+    /// every following failure up to `max_delay`. After `lockout_attempts` failures the username
+    /// is locked out for `lockout_duration` seconds.
+    pub fn block(&self, username: &str, config: &ThrottlingConfig, now: u64) -> Option<LoginBlock> {
+        let failed_login = self.0.get(username)?;
+
+        if failed_login.failures == 0 || Self::is_expired(failed_login, config, now) {
+            return None;
+        }
+
+        let (duration, locked_out) = if Self::is_locked_out(failed_login, config) {
+            (config.lockout_duration, true)
+        } else {
+            let doublings = (failed_login.failures - 1).min(63);
+            let delay = config.delay.saturating_mul(1 << doublings);
+            (delay.min(config.max_delay), false)
+        };
+
+        let until = failed_login.last_failure.saturating_add(duration);
+        (until > now).then_some(LoginBlock { until, locked_out })
+    }
+
+    pub fn record_failure(&mut self, username: &str, config: &ThrottlingConfig, now: u64) {
+        let failed_login = self.0.entry(username.to_string()).or_insert(FailedLogin {
+            failures: 0,
+            last_failure: now,
+        });
+
+        if Self::is_expired(failed_login, config, now) {
+            failed_login.failures = 0;
+        }
+
+        failed_login.failures = failed_login.failures.saturating_add(1);
+        failed_login.last_failure = now;
+
+        info!(
+            "Recorded failed login {} for '{}'",
+            failed_login.failures, username
+        );
+
+        // Forget all the other expired failures, so the file does not keep growing
+        self.0
+            .retain(|_, failed_login| !Self::is_expired(failed_login, config, now));
+    }
+
+    /// Forget the failures of a username. Returns whether there were any.
+    pub fn record_success(&mut self, username: &str) -> bool {
+        self.0.remove(username).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> ThrottlingConfig {
+        ThrottlingConfig {
+            delay: 2,
+            max_delay: 5,
+            reset_after: 100,
+            lockout_attempts: 4,
+            lockout_duration: 60,
+            failed_logins_path: String::new(),
+        }
+    }
+
+    #[test]
+    fn delays_and_lockout() {
+        let config = config();
+        let mut failed_logins = FailedLogins::default();
+
+        assert_eq!(failed_logins.block("lemur", &config, 1000), None);
+
+        failed_logins.record_failure("lemur", &config, 1000);
+        assert_eq!(
+            failed_logins.block("lemur", &config, 1001),
+            Some(LoginBlock {
+                until: 1002,
+                locked_out: false
+            })
+        );
+        assert_eq!(failed_logins.block("lemur", &config, 1002), None);
+        assert_eq!(failed_logins.block("other", &config, 1001), None);
+
+        // The delay doubles up to the maximum
+        failed_logins.record_failure("lemur", &config, 1010);
+        assert_eq!(
+            failed_logins.block("lemur", &config, 1010).map(|b| b.until),
+            Some(1014)
+        );
+        failed_logins.record_failure("lemur", &config, 1020);
+        assert_eq!(
+            failed_logins.block("lemur", &config, 1020).map(|b| b.until),
+            Some(1025)
+        );
+
+        failed_logins.record_failure("lemur", &config, 1030);
+        assert_eq!(
+            failed_logins.block("lemur", &config, 1030),
+            Some(LoginBlock {
+                until: 1090,
+                locked_out: true
+            })
+        );
+
+        // The failures are forgotten once the lockout is over
+        assert_eq!(failed_logins.block("lemur", &config, 1090), None);
+        failed_logins.record_failure("lemur", &config, 1090);
+        assert_eq!(
+            failed_logins.block("lemur", &config, 1090).map(|b| b.until),
+            Some(1092)
+        );
+
+        failed_logins.record_success("lemur");
+        assert_eq!(failed_logins.block("lemur", &config, 1090), None);
+    }
+
+    #[test]
+    fn reset_after() {
+        let config = config();
+        let mut failed_logins = FailedLogins::default();
+
+        failed_logins.record_failure("lemur", &config, 1000);
+        failed_logins.record_failure("lemur", &config, 1001);
+        failed_logins.record_failure("lemur", &config, 1200);
+        assert_eq!(
+            failed_logins.block("lemur", &config, 1200).map(|b| b.until),
+            Some(1202)
+        );
+    }
+
+    #[test]
+    fn file_format() {
+        let config = config();
+        let mut failed_logins = FailedLogins::default();
+        failed_logins.record_failure("lemur", &config, 1000);
+        failed_logins.record_failure("with space", &config, 1000);
+
+        let serialized = failed_logins.serialize();
+        assert_eq!(
+            serialized,
+            "6c656d7572 1 1000\n77697468207370616365 1 1000\n"
+        );
+
+        let parsed = FailedLogins::parse(&format!("{serialized}invalid line\n"));
+        assert_eq!(parsed.0, failed_logins.0);
+    }
+}
@@ -1,72 +1,150 @@
+use std::ffi::CString;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{Seek, SeekFrom, Write};
 use std::time::SystemTime;
 
-use libc::{c_char, utmpx};
-use log::{error, info};
+use libc::{c_char, timeval, utmpx};
+use log::{info, warn};
 
-pub struct UtmpxSession(utmpx);
+use crate::config::LoginRecordsConfig;
 
-pub fn add_utmpx_entry(username: &str, tty: u8, pid: u32) -> UtmpxSession {
-    info!("Adding UTMPX record");
+/// The size of a record in the lastlog file, i.e. `struct lastlog` of glibc
+const LASTLOG_RECORD_SIZE: u64 = 4 + 32 + 256;
+
+extern "C" {
+    // NOTE: This is part of glibc, but it is not exposed by the `libc` crate for Linux
+    fn updwtmpx(wtmpx_file: *const c_char, utmpx: *const utmpx);
+}
+
+#[derive(Debug)]
+pub enum UtmpxError {
+    /// The tty cannot be used for a login record
+    InvalidTty(u8),
+    InvalidSystemTime,
+}
+
+impl fmt::Display for UtmpxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UtmpxError::InvalidTty(tty) => write!(f, "Invalid TTY '{}'", tty),
+            UtmpxError::InvalidSystemTime => write!(f, "Invalid System Time"),
+        }
+    }
+}
+
+/// A login that is registered in the utmpx database and optionally in the wtmp file. The
+/// logout is registered when this is dropped.
+pub struct UtmpxSession {
+    entry: utmpx,
+    wtmp_path: Option<String>,
+}
+
+fn now_timeval() -> Result<timeval, UtmpxError> {
+    let epoch_duration = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_err(|_| UtmpxError::InvalidSystemTime)?
+        .as_micros();
 
+    Ok(timeval {
+        tv_sec: (epoch_duration / 1_000_000)
+            .try_into()
+            .map_err(|_| UtmpxError::InvalidSystemTime)?,
+        tv_usec: (epoch_duration % 1_000_000)
+            .try_into()
+            .map_err(|_| UtmpxError::InvalidSystemTime)?,
+    })
+}
+
+/// Copy a string into a fixed size field of a record, truncating it if it is too long
+fn set_field(field: &mut [c_char], value: &str) {
+    for (c, b) in field.iter_mut().zip(value.bytes()) {
+        *c = b as c_char;
+    }
+}
+
+/// Append a record to a wtmp formatted file (i.e. wtmp or btmp)
+fn append_record(path: &str, entry: &utmpx) {
+    let Ok(c_path) = CString::new(path) else {
+        warn!("Invalid path for login records '{}'", path);
+        return;
+    };
+
+    // NOTE: This silently does nothing when the file does not exist
+    unsafe { updwtmpx(c_path.as_ptr(), entry as *const utmpx) };
+}
+
+fn new_entry(
+    ut_type: libc::c_short,
+    username: &str,
+    tty: u8,
+    pid: u32,
+    host: Option<&str>,
+) -> Result<utmpx, UtmpxError> {
     // Check the MAN page for utmp for more information
     // `man utmp`
     //
     // https://man7.org/linux/man-pages/man0/utmpx.h.0p.html
     // https://github.com/fairyglade/ly/blob/master/src/login.c
-    let entry = {
-        let mut s: utmpx = unsafe { std::mem::zeroed() };
-
-        // ut_line    --- Device name of tty - "/dev/"
-        // ut_id      --- Terminal name suffix
-        // ut_user    --- Username
-        // ut_host    --- Hostname for remote login, or kernel version for run-level messages
-        // ut_exit    --- Exit status of a process marked as DEAD_PROCESS; not used by Linux init(1)
-        // ut_session --- Session ID (getsid(2)) used for windowing
-        // ut_tv {    --- Time entry was made
-        //     tv_sec     --- Seconds
-        //     tv_usec    --- Microseconds
-        // }
-        // ut_addr_v6 --- Internet address of remote
-
-        s.ut_type = libc::USER_PROCESS;
-        s.ut_pid = pid as libc::pid_t;
-
-        for (i, b) in username.as_bytes().iter().take(32).enumerate() {
-            s.ut_user[i] = *b as c_char;
-        }
+    let mut s: utmpx = unsafe { std::mem::zeroed() };
 
-        if tty > 12 {
-            error!("Invalid TTY");
-            std::process::exit(1);
-        }
-        let tty_c_char = (b'0' + tty) as c_char;
-
-        s.ut_line[0] = b't' as c_char;
-        s.ut_line[1] = b't' as c_char;
-        s.ut_line[2] = b'y' as c_char;
-        s.ut_line[3] = tty_c_char;
-
-        s.ut_id[0] = tty_c_char;
-
-        let epoch_duration = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap_or_else(|_| {
-                error!("Invalid System Time");
-                std::process::exit(1);
-            })
-            .as_micros();
-
-        s.ut_tv.tv_sec = (epoch_duration / 1_000_000).try_into().unwrap_or_else(|_| {
-            error!("Invalid System Time (TV_SEC Overflow)");
-            std::process::exit(1);
-        });
-        s.ut_tv.tv_usec = (epoch_duration % 1_000_000).try_into().unwrap_or_else(|_| {
-            error!("Invalid System Time (TV_USEC Overflow)");
-            std::process::exit(1);
-        });
+    // ut_line    --- Device name of tty - "/dev/"
+    // ut_id      --- Terminal name suffix
+    // ut_user    --- Username
+    // ut_host    --- Hostname for remote login, or kernel version for run-level messages
+    // ut_exit    --- Exit status of a process marked as DEAD_PROCESS; not used by Linux init(1)
+    // ut_session --- Session ID (getsid(2)) used for windowing
+    // ut_tv {    --- Time entry was made
+    //     tv_sec     --- Seconds
+    //     tv_usec    --- Microseconds
+    // }
+    // ut_addr_v6 --- Internet address of remote
 
-        s
-    };
+    s.ut_type = ut_type;
+    s.ut_pid = pid as libc::pid_t;
+
+    set_field(&mut s.ut_user, username);
+
+    // tty0 is the currently active tty and not a tty someone can log in on
+    if tty == 0 {
+        return Err(UtmpxError::InvalidTty(tty));
+    }
+
+    // The id is the tty number, like it is for `login` and `agetty`
+    set_field(&mut s.ut_line, &format!("tty{tty}"));
+    set_field(&mut s.ut_id, &tty.to_string());
+
+    if let Some(host) = host {
+        set_field(&mut s.ut_host, host);
+    }
+
+    let session = unsafe { libc::getsid(pid as libc::pid_t) };
+    if session < 0 {
+        warn!("Failed to get the session id of process {}", pid);
+    } else {
+        s.ut_session = session as _;
+    }
+
+    let tv = now_timeval()?;
+    s.ut_tv.tv_sec = tv.tv_sec as _;
+    s.ut_tv.tv_usec = tv.tv_usec as _;
+
+    Ok(s)
+}
+
+/// Register a login in the utmpx database and the wtmp file
+///
+/// The `host` is the X display for X sessions.
+pub fn add_utmpx_entry(
+    username: &str,
+    tty: u8,
+    pid: u32,
+    host: Option<&str>,
+    config: &LoginRecordsConfig,
+) -> Result<UtmpxSession, UtmpxError> {
+    info!("Adding UTMPX record");
+
+    let entry = new_entry(libc::USER_PROCESS, username, tty, pid, host)?;
 
     unsafe {
         libc::setutxent();
@@ -75,15 +153,99 @@ pub fn add_utmpx_entry(username: &str, tty: u8, pid: u32) -> UtmpxSession {
 
     info!("Added UTMPX record");
 
-    UtmpxSession(entry)
+    let wtmp_path = config.wtmp.then(|| config.wtmp_path.clone());
+    if let Some(wtmp_path) = &wtmp_path {
+        append_record(wtmp_path, &entry);
+        info!("Added login to '{}'", wtmp_path);
+    }
+
+    Ok(UtmpxSession { entry, wtmp_path })
+}
+
+/// Register a failed login attempt in the btmp file
+pub fn add_btmp_entry(username: &str, tty: u8, config: &LoginRecordsConfig) {
+    if !config.btmp {
+        return;
+    }
+
+    let entry = match new_entry(libc::LOGIN_PROCESS, username, tty, std::process::id(), None) {
+        Ok(entry) => entry,
+        Err(err) => {
+            warn!("Failed to create btmp record. Reason: {}", err);
+            return;
+        }
+    };
+    append_record(&config.btmp_path, &entry);
+
+    info!("Added failed login to '{}'", config.btmp_path);
+}
+
+/// Register the time and tty of a successful login of a user in the lastlog file
+pub fn update_lastlog(uid: u32, tty: u8, config: &LoginRecordsConfig) {
+    if !config.lastlog {
+        return;
+    }
+
+    let time = match now_timeval() {
+        Ok(tv) => tv.tv_sec as i32,
+        Err(err) => {
+            warn!("Failed to update lastlog. Reason: {}", err);
+            return;
+        }
+    };
+
+    // The record is `struct lastlog`: a 32-bit time, the tty and the host, which stays empty
+    let mut record = Vec::with_capacity(LASTLOG_RECORD_SIZE as usize);
+    record.extend_from_slice(&time.to_ne_bytes());
+    let mut line = format!("tty{tty}").into_bytes();
+    line.resize(32, 0);
+    record.extend_from_slice(&line);
+    record.resize(LASTLOG_RECORD_SIZE as usize, 0);
+
+    // The records are indexed by the uid
+    let result = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&config.lastlog_path)
+        .and_then(|mut file| {
+            file.seek(SeekFrom::Start(u64::from(uid) * LASTLOG_RECORD_SIZE))?;
+            file.write_all(&record)
+        });
+
+    match result {
+        Ok(()) => info!("Updated '{}'", config.lastlog_path),
+        Err(err) => warn!(
+            "Failed to update '{}'. Reason: {}",
+            config.lastlog_path, err
+        ),
+    }
 }
 
 impl Drop for UtmpxSession {
     fn drop(&mut self) {
-        let UtmpxSession(mut entry) = self;
+        let entry = &mut self.entry;
 
         info!("Removing UTMPX record");
 
+        // The logout in wtmp keeps the line, so it can be matched with the login
+        if let Some(wtmp_path) = &self.wtmp_path {
+            let mut logout = *entry;
+            logout.ut_type = libc::DEAD_PROCESS;
+            logout.ut_user = <[c_char; 32]>::default();
+
+            match now_timeval() {
+                Ok(tv) => {
+                    logout.ut_tv.tv_sec = tv.tv_sec as _;
+                    logout.ut_tv.tv_usec = tv.tv_usec as _;
+
+                    append_record(wtmp_path, &logout);
+                    info!("Added logout to '{}'", wtmp_path);
+                }
+                Err(err) => warn!("Failed to add logout to '{}'. Reason: {}", wtmp_path, err),
+            }
+        }
+
         entry.ut_type = libc::DEAD_PROCESS;
 
         entry.ut_line = <[c_char; 32]>::default();
@@ -94,8 +256,117 @@ impl Drop for UtmpxSession {
 
         unsafe {
             libc::setutxent();
-            libc::pututxline(&entry as *const utmpx);
+            libc::pututxline(entry as *const utmpx);
             libc::endutxent();
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("lemurs-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn config(dir: &std::path::Path) -> LoginRecordsConfig {
+        let path = |file: &str| dir.join(file).to_str().unwrap().to_string();
+
+        LoginRecordsConfig {
+            wtmp: true,
+            wtmp_path: path("wtmp"),
+            btmp: true,
+            btmp_path: path("btmp"),
+            lastlog: true,
+            lastlog_path: path("lastlog"),
+        }
+    }
+
+    fn field(value: &[c_char]) -> String {
+        value
+            .iter()
+            .take_while(|c| **c != 0)
+            .map(|c| *c as u8 as char)
+            .collect()
+    }
+
+    #[test]
+    fn entry_fields() {
+        let pid = std::process::id();
+
+        let entry = new_entry(libc::USER_PROCESS, "lemur", 12, pid, Some(":1")).unwrap();
+        assert_eq!(field(&entry.ut_line), "tty12");
+        assert_eq!(field(&entry.ut_id), "12");
+        assert_eq!(field(&entry.ut_user), "lemur");
+        assert_eq!(field(&entry.ut_host), ":1");
+        assert_eq!(entry.ut_session as libc::pid_t, unsafe { libc::getsid(0) });
+
+        let entry = new_entry(libc::USER_PROCESS, &"a".repeat(40), 255, pid, None).unwrap();
+        assert_eq!(field(&entry.ut_line), "tty255");
+        assert_eq!(field(&entry.ut_id), "255");
+        assert_eq!(entry.ut_user, [b'a' as c_char; 32]);
+        assert_eq!(field(&entry.ut_host), "");
+
+        assert!(matches!(
+            new_entry(libc::USER_PROCESS, "lemur", 0, pid, None),
+            Err(UtmpxError::InvalidTty(0))
+        ));
+    }
+
+    #[test]
+    fn btmp_records() {
+        let dir = temp_dir("btmp");
+        let config = config(&dir);
+        fs::write(&config.btmp_path, []).unwrap();
+
+        add_btmp_entry("lemur", 2, &config);
+        add_btmp_entry("other", 2, &config);
+
+        let contents = fs::read(&config.btmp_path).unwrap();
+        assert_eq!(contents.len(), 2 * std::mem::size_of::<utmpx>());
+
+        let entry: utmpx = unsafe { std::ptr::read_unaligned(contents.as_ptr() as *const utmpx) };
+        assert_eq!(entry.ut_type, libc::LOGIN_PROCESS);
+        assert_eq!(entry.ut_user[..6], b"lemur\0".map(|b| b as c_char));
+        assert_eq!(entry.ut_line[..5], b"tty2\0".map(|b| b as c_char));
+
+        // Nothing is written when it is disabled
+        add_btmp_entry(
+            "lemur",
+            2,
+            &LoginRecordsConfig {
+                btmp: false,
+                ..config.clone()
+            },
+        );
+        assert_eq!(fs::read(&config.btmp_path).unwrap().len(), contents.len());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn lastlog_records() {
+        let dir = temp_dir("lastlog");
+        let config = config(&dir);
+
+        update_lastlog(3, 7, &config);
+
+        let contents = fs::read(&config.lastlog_path).unwrap();
+        assert_eq!(contents.len() as u64, 4 * LASTLOG_RECORD_SIZE);
+
+        let record = &contents[3 * LASTLOG_RECORD_SIZE as usize..];
+        let time = i32::from_ne_bytes(record[..4].try_into().unwrap());
+        assert!(time > 0);
+        assert_eq!(&record[4..9], b"tty7\0");
+        assert!(contents[..3 * LASTLOG_RECORD_SIZE as usize]
+            .iter()
+            .all(|b| *b == 0));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,16 +1,40 @@
-use std::fs::File;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
 use std::io::{self, BufReader, Read};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::RwLock;
 
-use crossterm::event::KeyCode;
+use chrono::format::{Item, StrftimeItems};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use lazy_static::lazy_static;
 use log::error;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use toml::value::{Table, Value};
 
 use tui::style::{Color, Modifier};
 
+lazy_static! {
+    /// The named colors of the `[palette]` section
+    static ref PALETTE: RwLock<BTreeMap<String, String>> = RwLock::new(BTreeMap::new());
+}
+
+/// Make the named colors of a palette usable everywhere a color can be given
+pub fn set_palette(palette: &Palette) {
+    match PALETTE.write() {
+        Ok(mut guard) => *guard = palette.0.clone(),
+        Err(err) => error!("Failed to set the palette. Reason: {}", err),
+    }
+}
+
+fn palette_color(name: &str) -> Option<Color> {
+    let palette = PALETTE.read().ok()?;
+    str_to_color(palette.get(name.trim())?)
+}
+
 pub fn get_color(color: &str) -> Color {
-    if let Some(color) = str_to_color(color) {
+    if let Some(color) = str_to_color(color).or_else(|| palette_color(color)) {
         color
     } else {
         error!("Did not recognize the color '{}'", color);
@@ -18,11 +42,24 @@ pub fn get_color(color: &str) -> Color {
     }
 }
 
+/// Whether a color string can only be a name in the palette
+fn is_palette_name(color: &str) -> bool {
+    let color = color.trim();
+    color.starts_with(|c: char| c.is_ascii_alphabetic())
+        && !color.to_lowercase().starts_with("rgb(")
+        && color
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | ' '))
+}
+
 fn str_to_color(color: &str) -> Option<Color> {
     use Color::*;
 
-    let c = color.to_lowercase();
+    let c = color.trim().to_lowercase();
     Some(match &c[..] {
+        // The color of the terminal
+        "reset" | "default" => Reset,
+
         // TUI colors
         "black" => Black,
         "red" => Red,
@@ -44,28 +81,47 @@ fn str_to_color(color: &str) -> Option<Color> {
         // Custom colors
         "orange" => Rgb(255, 127, 0),
 
-        // Hex and unknown
-        c => {
-            if !c.starts_with('#') || c.len() != 7 {
-                return None;
+        // Hex
+        c if c.starts_with('#') => {
+            let hex_byte = |hex: &str| hex::decode(hex).ok().and_then(|mut bytes| bytes.pop());
+
+            match c.len() {
+                // `#rgb` is short for `#rrggbb`
+                4 => {
+                    let digit = |i: usize| hex_byte(&c[i..i + 1].repeat(2));
+                    Rgb(digit(1)?, digit(2)?, digit(3)?)
+                }
+                7 => Rgb(
+                    hex_byte(&c[1..3])?,
+                    hex_byte(&c[3..5])?,
+                    hex_byte(&c[5..7])?,
+                ),
+                _ => return None,
             }
+        }
+
+        // `rgb(r, g, b)` in decimal
+        c if c.starts_with("rgb(") && c.ends_with(')') => {
+            let mut components = c[4..c.len() - 1].split(',').map(|x| x.trim().parse().ok());
 
-            let r = hex::decode(&c[1..3])
-                .ok()
-                .and_then(|mut bytes| bytes.pop())?;
-            let g = hex::decode(&c[3..5])
-                .ok()
-                .and_then(|mut bytes| bytes.pop())?;
-            let b = hex::decode(&c[5..7])
-                .ok()
-                .and_then(|mut bytes| bytes.pop())?;
+            let color = Rgb(
+                components.next()??,
+                components.next()??,
+                components.next()??,
+            );
+            if components.next().is_some() {
+                return None;
+            }
 
-            Rgb(r, g, b)
+            color
         }
+
+        // ANSI 256-color index and unknown
+        c => Indexed(c.parse().ok()?),
     })
 }
 
-fn get_modifier(modifier: &str) -> Option<Modifier> {
+pub fn get_modifier(modifier: &str) -> Option<Modifier> {
     let m = modifier.trim().to_lowercase();
     Some(match &m[..] {
         "bold" => Modifier::BOLD,
@@ -93,23 +149,367 @@ pub fn get_modifiers(modifiers: &str) -> Vec<Modifier> {
     ms
 }
 
-pub fn get_key(key: &str) -> KeyCode {
-    match key.trim() {
-        "F1" => KeyCode::F(1),
-        "F2" => KeyCode::F(2),
-        "F3" => KeyCode::F(3),
-        "F4" => KeyCode::F(4),
-        "F5" => KeyCode::F(5),
-        "F6" => KeyCode::F(6),
-        "F7" => KeyCode::F(7),
-        "F8" => KeyCode::F(8),
-        "F9" => KeyCode::F(9),
-        "F10" => KeyCode::F(10),
-        "F11" => KeyCode::F(11),
-        "F12" => KeyCode::F(12),
-        // TODO: Add others
-        _ => KeyCode::F(255),
+/// A key combination such as `F1`, `Ctrl-c` or `Alt-Shift-Left`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeySpec {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl KeySpec {
+    /// Parse a key specification, which is a key name with any number of `Ctrl-`, `Alt-` and
+    /// `Shift-` prefixes
+    pub fn parse(spec: &str) -> Option<KeySpec> {
+        let mut rest = spec.trim();
+        let mut modifiers = KeyModifiers::NONE;
+
+        loop {
+            let (prefix, modifier) = match rest.split_once('-') {
+                // `Ctrl--` is the `-` key with control
+                Some((prefix, name)) if !prefix.is_empty() && !name.is_empty() => {
+                    match &prefix.to_lowercase()[..] {
+                        "ctrl" | "control" | "c" => (prefix, KeyModifiers::CONTROL),
+                        "alt" | "meta" | "m" => (prefix, KeyModifiers::ALT),
+                        "shift" | "s" => (prefix, KeyModifiers::SHIFT),
+                        _ => break,
+                    }
+                }
+                _ => break,
+            };
+
+            modifiers |= modifier;
+            rest = &rest[prefix.len() + 1..];
+        }
+
+        let code = match str_to_key_code(rest)? {
+            // `Ctrl-C` is written for the letter that terminals report as `c` with control, as
+            // only shift makes it upper case
+            KeyCode::Char(c)
+                if !modifiers.is_empty() && !modifiers.contains(KeyModifiers::SHIFT) =>
+            {
+                KeyCode::Char(c.to_ascii_lowercase())
+            }
+            code => code,
+        };
+        Some(KeySpec::normalized(code, modifiers))
+    }
+
+    /// Whether a key event from the terminal is this key combination
+    pub fn matches(&self, event: &KeyEvent) -> bool {
+        *self == KeySpec::normalized(event.code, event.modifiers)
+    }
+
+    /// Terminals report the shift state of characters and tabs in different ways, so these are
+    /// brought into the same form.
+    fn normalized(code: KeyCode, modifiers: KeyModifiers) -> KeySpec {
+        let shift = modifiers.contains(KeyModifiers::SHIFT);
+        match code {
+            KeyCode::Char(c) => KeySpec {
+                code: KeyCode::Char(if shift { c.to_ascii_uppercase() } else { c }),
+                modifiers: modifiers - KeyModifiers::SHIFT,
+            },
+            KeyCode::Tab if shift => KeySpec {
+                code: KeyCode::BackTab,
+                modifiers: modifiers - KeyModifiers::SHIFT,
+            },
+            KeyCode::BackTab => KeySpec {
+                code,
+                modifiers: modifiers - KeyModifiers::SHIFT,
+            },
+            code => KeySpec { code, modifiers },
+        }
+    }
+}
+
+fn str_to_key_code(key: &str) -> Option<KeyCode> {
+    let mut chars = key.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return (!c.is_control()).then_some(KeyCode::Char(c));
+    }
+
+    let k = key.to_lowercase();
+    Some(match &k[..] {
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "esc" | "escape" => KeyCode::Esc,
+        "enter" | "return" => KeyCode::Enter,
+        "tab" => KeyCode::Tab,
+        "backtab" => KeyCode::BackTab,
+        "backspace" => KeyCode::Backspace,
+        "delete" | "del" => KeyCode::Delete,
+        "insert" | "ins" => KeyCode::Insert,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        "space" => KeyCode::Char(' '),
+        "minus" => KeyCode::Char('-'),
+
+        // Function keys
+        k => {
+            let n = k.strip_prefix('f')?.parse().ok()?;
+            if !(1..=24).contains(&n) {
+                return None;
+            }
+
+            KeyCode::F(n)
+        }
+    })
+}
+
+/// The directory with the themes that can be installed
+pub const THEMES_PATH: &str = "/etc/lemurs/themes";
+
+const BUILTIN_THEMES: &[(&str, &str)] = &[
+    ("default", include_str!("../extra/themes/default.toml")),
+    (
+        "high-contrast",
+        include_str!("../extra/themes/high-contrast.toml"),
+    ),
+    (
+        "monochrome",
+        include_str!("../extra/themes/monochrome.toml"),
+    ),
+    ("solarized", include_str!("../extra/themes/solarized.toml")),
+];
+
+/// Load a theme from the themes directory or from the built-in themes
+///
+/// Returns where the theme was loaded from together with the parsed theme.
+pub fn load_theme(name: &str) -> Result<(ConfigOrigin, ParsedConfig), String> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+        || name.starts_with('.')
+    {
+        return Err(format!("'{}' is not a valid theme name", name));
+    }
+
+    let path = Path::new(THEMES_PATH).join(format!("{}.toml", name));
+    match fs::read_to_string(&path) {
+        Ok(contents) => {
+            return Ok((
+                ConfigOrigin::file(&path),
+                PartialConfig::parse_theme(&contents),
+            ))
+        }
+        Err(err) if err.kind() != io::ErrorKind::NotFound => {
+            return Err(format!(
+                "The theme '{}' cannot be loaded. Reason: {}",
+                path.display(),
+                err
+            ));
+        }
+        Err(_) => {}
+    }
+
+    BUILTIN_THEMES
+        .iter()
+        .find(|(builtin, _)| *builtin == name)
+        .map(|(_, contents)| {
+            (
+                ConfigOrigin::other(format!("built-in theme '{}'", name)),
+                PartialConfig::parse_theme(contents),
+            )
+        })
+        .ok_or_else(|| format!("There is no theme named '{}'", name))
+}
+
+/// The TTYs that the kernel can have (`MAX_NR_CONSOLES`)
+const TTY_RANGE: std::ops::RangeInclusive<i64> = 1..=63;
+
+/// A problem within a configuration file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigProblem {
+    /// The dotted TOML path of the key with the problem
+    pub key: String,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: ", line)?,
+            None => write!(f, "unknown line: ")?,
+        }
+
+        if !self.key.is_empty() {
+            write!(f, "'{}': ", self.key)?;
+        }
+
+        write!(f, "{}", self.message)
+    }
+}
+
+fn key_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+fn is_color_key(key: &str) -> bool {
+    key.ends_with("_color") || key.ends_with("_color_focused")
+}
+
+/// Whether a dotted key path is a color, a modifier or a palette entry, which a theme can set
+fn is_style_key(key_path: &str) -> bool {
+    let key = key_path.rsplit('.').next().unwrap_or(key_path);
+
+    key_path.starts_with("palette.")
+        || is_color_key(key)
+        || key.ends_with("_modifiers")
+        || key.ends_with("_modifiers_focused")
+}
+
+/// Remove the keys of a theme that are not a style, so that a theme cannot change the behaviour
+fn retain_style_keys(table: &mut Table, path: &str, problems: &mut Vec<ConfigProblem>) {
+    retain_entries(table, |key, value| {
+        let key_path = key_path(path, key);
+        match value {
+            Value::Table(table) if key_path != "palette" => {
+                retain_style_keys(table, &key_path, problems);
+                true
+            }
+            _ if is_style_key(&key_path) || key_path == "palette" => true,
+            _ => {
+                problems.push(ConfigProblem {
+                    key: key_path,
+                    line: None,
+                    message: String::from("A theme can only set colors and modifiers"),
+                });
+                false
+            }
+        }
+    });
+}
+
+/// Check that a TTY number exists
+pub fn check_tty(tty: i64) -> Result<(), String> {
+    if !TTY_RANGE.contains(&tty) {
+        return Err(format!(
+            "TTY {} is not within {} to {}",
+            tty,
+            TTY_RANGE.start(),
+            TTY_RANGE.end()
+        ));
     }
+
+    Ok(())
+}
+
+/// Check the value of a known key beyond its type
+fn check_value(path: &str, key: &str, value: &Value) -> Result<(), String> {
+    if path == "tty" {
+        check_tty(value.as_integer().unwrap_or_default())?;
+    }
+
+    if path == "layout.order" {
+        let widgets: Vec<_> = value.as_array().into_iter().flatten().collect();
+        if let Some((i, widget)) = widgets
+            .iter()
+            .enumerate()
+            .find(|(i, widget)| widgets[..*i].contains(widget))
+        {
+            return Err(format!("{} is in the order twice (at {})", widget, i));
+        }
+    }
+
+    if path.starts_with("keybindings.") {
+        for spec in value
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+        {
+            if KeySpec::parse(spec).is_none() {
+                return Err(format!("'{}' is not a supported key", spec));
+            }
+        }
+    }
+
+    let value = match value.as_str() {
+        Some(value) => value,
+        None => return Ok(()),
+    };
+
+    if path.starts_with("palette.") {
+        if str_to_color(value).is_none() {
+            return Err(format!("'{}' is not a color", value));
+        }
+    } else if is_color_key(key) {
+        // Names in the palette are checked once all files are merged
+        if str_to_color(value).is_none() && !is_palette_name(value) {
+            return Err(format!("'{}' is not a color", value));
+        }
+    } else if key.ends_with("_modifiers") || key.ends_with("_modifiers_focused") {
+        for modifier in value.split(',').filter(|m| !m.trim().is_empty()) {
+            if get_modifier(modifier).is_none() {
+                return Err(format!("'{}' is not a modifier", modifier.trim()));
+            }
+        }
+    } else if key.ends_with("_key") && KeySpec::parse(value).is_none() {
+        return Err(format!("'{}' is not a supported key", value));
+    } else if path == "status_bar.clock_format"
+        && StrftimeItems::new(value).any(|item| item == Item::Error)
+    {
+        return Err(format!("'{}' is not a valid time format", value));
+    }
+
+    Ok(())
+}
+
+/// Keep only the entries of a table for which `f` returns `true`
+fn retain_entries(table: &mut Table, mut f: impl FnMut(&str, &mut Value) -> bool) {
+    *table = std::mem::take(table)
+        .into_iter()
+        .filter_map(|(key, mut value)| f(&key, &mut value).then_some((key, value)))
+        .collect();
+}
+
+/// Checks the keys and values of a table within a configuration file. Keys with a problem are
+/// removed, so that the defaults are used for them instead.
+trait ValidateTable {
+    fn validate_table(table: &mut Table, path: &str, problems: &mut Vec<ConfigProblem>);
+}
+
+impl ValidateTable for BTreeMap<String, String> {
+    fn validate_table(table: &mut Table, path: &str, problems: &mut Vec<ConfigProblem>) {
+        retain_entries(table, |key, value| {
+            let is_string = value.is_str();
+            if !is_string {
+                problems.push(ConfigProblem {
+                    key: key_path(path, key),
+                    line: None,
+                    message: format!("Expected a string, found a {}", value.type_str()),
+                });
+            }
+            is_string
+        });
+    }
+}
+
+macro_rules! validate_field {
+    ($value:ident, $path:ident, $key:expr, $problems:ident, $field_type:ty) => {
+        match $value.clone().try_into::<$field_type>() {
+            Ok(_) => check_value(&$path, $key, $value),
+            Err(err) => Err(format!("Invalid value. {}", err)),
+        }
+    };
+    ($value:ident, $path:ident, $key:expr, $problems:ident, $field_type:ty, $par_field_type:ty) => {
+        match $value {
+            Value::Table(table) => {
+                <$par_field_type as ValidateTable>::validate_table(table, &$path, $problems);
+                Ok(())
+            }
+            value => Err(format!("Expected a table, found a {}", value.type_str())),
+        }
+    };
 }
 
 macro_rules! partial_struct_field {
@@ -132,7 +532,7 @@ macro_rules! merge_strategy {
 
 macro_rules! toml_config_struct {
     ($struct_name:ident, $partial_struct_name:ident, $($field_name:ident => $field_type:ty $([$par_field_type:ty])?),+ $(,)?) => {
-        #[derive(Debug, Clone, Deserialize)]
+        #[derive(Debug, Clone, Deserialize, Serialize)]
         pub struct $struct_name {
             $(pub $field_name: $field_type,)+
         }
@@ -149,18 +549,105 @@ macro_rules! toml_config_struct {
                 )+
             }
         }
+        impl ValidateTable for $partial_struct_name {
+            fn validate_table(table: &mut Table, path: &str, problems: &mut Vec<ConfigProblem>) {
+                retain_entries(table, |key, value| {
+                    let key_path = key_path(path, key);
+                    let result = match key {
+                        $(
+                        stringify!($field_name) => validate_field!(
+                            value, key_path, key, problems, $field_type $(, $par_field_type)?
+                        ),
+                        )+
+                        _ => Err(String::from("Unknown key")),
+                    };
+
+                    match result {
+                        Ok(()) => true,
+                        Err(message) => {
+                            problems.push(ConfigProblem {
+                                key: key_path,
+                                line: None,
+                                message,
+                            });
+                            false
+                        }
+                    }
+                });
+            }
+        }
     }
 }
 
 toml_config_struct! { Config, PartialConfig,
     tty => u8,
 
+    pam_service => String,
+
+    cache_path => String,
+
     focus_behaviour => FocusBehaviour,
 
+    theme => String,
+
+    layout => LayoutConfig [PartialLayoutConfig],
+    banner => BannerConfig [PartialBannerConfig],
+    status_bar => StatusBarConfig [PartialStatusBarConfig],
+
     power_controls => PowerControlConfig [PartialPowerControlConfig],
     environment_switcher => SwitcherConfig [PartialSwitcherConfig],
     username_field => UsernameFieldConfig [PartialUsernameFieldConfig],
     password_field => PasswordFieldConfig [PartialPasswordFieldConfig],
+
+    keybindings => KeyBindingsConfig [PartialKeyBindingsConfig],
+
+    palette => Palette [Palette],
+
+    authentication => AuthenticationConfig [PartialAuthenticationConfig],
+    login_records => LoginRecordsConfig [PartialLoginRecordsConfig],
+
+    x11 => X11Config [PartialX11Config],
+    wayland => WaylandConfig [PartialWaylandConfig],
+
+    environment => EnvironmentConfig [PartialEnvironmentConfig],
+}
+
+toml_config_struct! { LayoutConfig, PartialLayoutConfig,
+    vertical_alignment => VerticalAlignment,
+    horizontal_alignment => HorizontalAlignment,
+
+    order => Vec<FormWidget>,
+    spacing => u16,
+
+    horizontal_margin => u16,
+    vertical_margin => u16,
+    max_width => u16,
+
+    show_border => bool,
+    border_title => String,
+    border_color => String,
+}
+
+toml_config_struct! { BannerConfig, PartialBannerConfig,
+    show => bool,
+    file => String,
+    text => String,
+    text_color => String,
+    text_modifiers => String,
+    alignment => HorizontalAlignment,
+}
+
+toml_config_struct! { StatusBarConfig, PartialStatusBarConfig,
+    show => bool,
+    position => StatusBarPosition,
+    alignment => HorizontalAlignment,
+
+    items => Vec<StatusBarItem>,
+    separator => String,
+    clock_format => String,
+
+    text_color => String,
+    text_modifiers => String,
 }
 
 toml_config_struct! { PowerControlConfig, PartialPowerControlConfig,
@@ -253,7 +740,186 @@ toml_config_struct! { PasswordFieldConfig, PartialPasswordFieldConfig,
     style => InputFieldStyle [PartialInputFieldStyle],
 }
 
-#[derive(Debug, Clone, Deserialize)]
+toml_config_struct! { KeyBindingsConfig, PartialKeyBindingsConfig,
+    preset => KeyBindingsPreset,
+    next_field => Option<Vec<String>>,
+    prev_field => Option<Vec<String>>,
+    submit => Option<Vec<String>>,
+    switcher_left => Option<Vec<String>>,
+    switcher_right => Option<Vec<String>>,
+    clear_field => Option<Vec<String>>,
+    save_cache => Option<Vec<String>>,
+    shutdown => Option<Vec<String>>,
+    reboot => Option<Vec<String>>,
+}
+
+toml_config_struct! { AuthenticationConfig, PartialAuthenticationConfig,
+    show_reasons => bool,
+    messages => AuthenticationMessages [PartialAuthenticationMessages],
+    throttling => ThrottlingConfig [PartialThrottlingConfig],
+}
+
+toml_config_struct! { AuthenticationMessages, PartialAuthenticationMessages,
+    failed => String,
+    account_expired => String,
+    account_locked => String,
+    access_denied => String,
+    unknown_user => String,
+    session => String,
+    pam_service => String,
+}
+
+toml_config_struct! { ThrottlingConfig, PartialThrottlingConfig,
+    delay => u64,
+    max_delay => u64,
+    reset_after => u64,
+    lockout_attempts => u32,
+    lockout_duration => u64,
+    failed_logins_path => String,
+}
+
+toml_config_struct! { LoginRecordsConfig, PartialLoginRecordsConfig,
+    wtmp => bool,
+    wtmp_path => String,
+    btmp => bool,
+    btmp_path => String,
+    lastlog => bool,
+    lastlog_path => String,
+}
+
+toml_config_struct! { X11Config, PartialX11Config,
+    scripts_path => String,
+    xsessions_path => String,
+}
+
+toml_config_struct! { WaylandConfig, PartialWaylandConfig,
+    scripts_path => String,
+    wayland_sessions_path => String,
+}
+
+/// Tables of which the keys are not known beforehand are merged key by key
+trait MergeTable {
+    fn merge_in_partial(&mut self, partial: Self);
+}
+
+impl MergeTable for BTreeMap<String, String> {
+    fn merge_in_partial(&mut self, partial: Self) {
+        self.extend(partial)
+    }
+}
+
+/// Named colors that can be used in place of every color
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Palette(BTreeMap<String, String>);
+
+impl MergeTable for Palette {
+    fn merge_in_partial(&mut self, partial: Self) {
+        self.0.extend(partial.0)
+    }
+}
+
+impl ValidateTable for Palette {
+    fn validate_table(table: &mut Table, path: &str, problems: &mut Vec<ConfigProblem>) {
+        retain_entries(table, |key, value| {
+            let result = match value {
+                Value::String(_) if !is_palette_name(key) || str_to_color(key).is_some() => {
+                    Err(format!("'{}' cannot be used as the name of a color", key))
+                }
+                Value::String(color) if str_to_color(color).is_none() => {
+                    Err(format!("'{}' is not a color", color))
+                }
+                Value::String(_) => Ok(()),
+                value => Err(format!("Expected a string, found a {}", value.type_str())),
+            };
+
+            result
+                .map_err(|message| {
+                    problems.push(ConfigProblem {
+                        key: key_path(path, key),
+                        line: None,
+                        message,
+                    })
+                })
+                .is_ok()
+        });
+    }
+}
+
+toml_config_struct! { EnvironmentConfig, PartialEnvironmentConfig,
+    set => BTreeMap<String, String> [BTreeMap<String, String>],
+    prepend => BTreeMap<String, String> [BTreeMap<String, String>],
+    append => BTreeMap<String, String> [BTreeMap<String, String>],
+    unset => Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum VerticalAlignment {
+    #[serde(rename = "top")]
+    Top,
+    #[serde(rename = "center")]
+    Center,
+    #[serde(rename = "bottom")]
+    Bottom,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum HorizontalAlignment {
+    #[serde(rename = "left")]
+    Left,
+    #[serde(rename = "center")]
+    Center,
+    #[serde(rename = "right")]
+    Right,
+}
+
+/// The widgets of the login form that can be ordered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum FormWidget {
+    #[serde(rename = "switcher")]
+    Switcher,
+    #[serde(rename = "username")]
+    Username,
+    #[serde(rename = "password")]
+    Password,
+    #[serde(rename = "status_message")]
+    StatusMessage,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum StatusBarPosition {
+    #[serde(rename = "top")]
+    Top,
+    #[serde(rename = "bottom")]
+    Bottom,
+}
+
+/// The information that can be shown in the status bar
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum StatusBarItem {
+    #[serde(rename = "clock")]
+    Clock,
+    #[serde(rename = "hostname")]
+    Hostname,
+    #[serde(rename = "kernel")]
+    Kernel,
+    #[serde(rename = "uptime")]
+    Uptime,
+    #[serde(rename = "tty")]
+    Tty,
+    #[serde(rename = "battery")]
+    Battery,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub enum KeyBindingsPreset {
+    #[serde(rename = "default")]
+    Default,
+    #[serde(rename = "vi")]
+    Vi,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub enum FocusBehaviour {
     #[serde(rename = "default")]
     FirstNonCached,
@@ -276,12 +942,526 @@ impl Default for Config {
     }
 }
 
+/// The dotted key path that is defined on every line of the contents of a TOML file
+///
+/// This only understands the subset of TOML that is used by the configuration: table headers and
+/// `key = value` lines.
+fn key_lines(contents: &str) -> Vec<Option<String>> {
+    let unquote = |part: &str| part.trim().trim_matches('"').trim_matches('\'').to_string();
+    let join = |parts: &str| parts.split('.').map(unquote).collect::<Vec<_>>().join(".");
+
+    let mut table = String::new();
+    contents
+        .lines()
+        .map(|line| {
+            let line = line.trim();
+
+            if line.starts_with('[') {
+                let header = line
+                    .trim_start_matches('[')
+                    .split(']')
+                    .next()
+                    .filter(|_| line.contains(']'))?;
+                table = join(header);
+                Some(table.clone())
+            } else if line.starts_with('#') {
+                None
+            } else {
+                let (line_key, _) = line.split_once('=')?;
+                Some(key_path(&table, &join(line_key)))
+            }
+        })
+        .collect()
+}
+
+/// Find the line of a dotted key path within the contents of a TOML file
+fn find_key_line(contents: &str, key: &str) -> Option<usize> {
+    key_lines(contents)
+        .iter()
+        .position(|line_key| line_key.as_deref() == Some(key))
+        .map(|i| i + 1)
+}
+
+/// Collect the dotted key paths of all values within a table
+fn leaf_keys(table: &Table, path: &str, keys: &mut Vec<String>) {
+    for (key, value) in table {
+        let key_path = key_path(path, key);
+        match value {
+            Value::Table(table) => leaf_keys(table, &key_path, keys),
+            _ => keys.push(key_path),
+        }
+    }
+}
+
+/// Where configuration values come from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigOrigin {
+    /// How the origin is shown (e.g. the path of a file, `--theme` or `default`)
+    pub label: String,
+    /// The file that the values are read from, if there is one
+    pub path: Option<PathBuf>,
+}
+
+impl ConfigOrigin {
+    pub fn file(path: &Path) -> Self {
+        Self {
+            label: path.display().to_string(),
+            path: Some(path.to_path_buf()),
+        }
+    }
+
+    pub fn other(label: impl Into<String>) -> Self {
+        Self {
+            label: label.into(),
+            path: None,
+        }
+    }
+
+    /// The line of a dotted key path within the file of the origin
+    pub fn find_key_line(&self, key: &str) -> Option<usize> {
+        let contents = fs::read_to_string(self.path.as_ref()?).ok()?;
+        find_key_line(&contents, key)
+    }
+}
+
+impl fmt::Display for ConfigOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.label)
+    }
+}
+
+/// The result of parsing a configuration file
+pub struct ParsedConfig {
+    pub partial: PartialConfig,
+    /// The dotted key paths of the values that are set by the file
+    pub keys: Vec<String>,
+    pub problems: Vec<ConfigProblem>,
+    /// The valid values of the file, which `partial` is made from
+    table: Table,
+}
+
+/// The value at a dotted key path within a table
+fn value_at<'a>(table: &'a Table, key_path: &str) -> Option<&'a Value> {
+    let mut parts = key_path.split('.');
+    let mut value = table.get(parts.next()?)?;
+    for part in parts {
+        value = value.as_table()?.get(part)?;
+    }
+    Some(value)
+}
+
+/// Remove the value at a dotted key path from a table
+fn remove_value_at(table: &mut Table, key_path: &str) {
+    match key_path.split_once('.') {
+        Some((key, rest)) => {
+            if let Some(Value::Table(table)) = table.get_mut(key) {
+                remove_value_at(table, rest);
+            }
+        }
+        None => {
+            table.remove(key_path);
+        }
+    }
+}
+
+impl ParsedConfig {
+    /// Leave out the styles that are set to their default value
+    ///
+    /// The installed configuration file sets every style to its default, which would otherwise
+    /// override every style of a theme.
+    pub fn without_default_styles(mut self, defaults: &Config) -> Self {
+        let defaults = match Value::try_from(defaults) {
+            Ok(Value::Table(defaults)) => defaults,
+            _ => return self,
+        };
+
+        let table = &mut self.table;
+        self.keys.retain(|key| {
+            let is_default = is_style_key(key)
+                && value_at(table, key).is_some()
+                && value_at(table, key) == value_at(&defaults, key);
+            if is_default {
+                remove_value_at(table, key);
+            }
+            !is_default
+        });
+
+        if let Ok(partial) = Value::Table(self.table.clone()).try_into() {
+            self.partial = partial;
+        }
+        self
+    }
+}
+
+impl Config {
+    /// Serialize the configuration to TOML
+    ///
+    /// When `origins` is given, every value is annotated with the file it was set by, or with
+    /// `default` if no file set it.
+    pub fn dump(
+        &self,
+        origins: Option<&BTreeMap<String, ConfigOrigin>>,
+    ) -> Result<String, toml::ser::Error> {
+        let contents = toml::to_string(&Value::try_from(self)?)?;
+
+        let origins = match origins {
+            Some(origins) => origins,
+            None => return Ok(contents),
+        };
+
+        let mut keys = Vec::new();
+        if let Value::Table(table) = Value::try_from(self)? {
+            leaf_keys(&table, "", &mut keys);
+        }
+
+        let mut annotated = String::new();
+        for (line, line_key) in contents.lines().zip(key_lines(&contents)) {
+            annotated.push_str(line);
+
+            if let Some(line_key) = line_key.filter(|line_key| keys.contains(line_key)) {
+                let origin = origins
+                    .get(&line_key)
+                    .map_or("default", |origin| origin.label.as_str());
+                annotated.push_str(" # ");
+                annotated.push_str(origin);
+            }
+
+            annotated.push('\n');
+        }
+
+        Ok(annotated)
+    }
+
+    /// Find the colors that are neither a color nor a name in the palette
+    ///
+    /// This can only be checked after merging, since the palette can be in another file than
+    /// where its names are used.
+    pub fn check_palette_names(&self) -> Vec<ConfigProblem> {
+        let mut keys = Vec::new();
+        let value = Value::try_from(self).unwrap_or_else(|_| Value::Table(Table::new()));
+        if let Value::Table(table) = &value {
+            leaf_keys(table, "", &mut keys);
+        }
+
+        keys.into_iter()
+            .filter_map(|key| {
+                let name = key.rsplit('.').next()?;
+                if !is_color_key(name) || key.starts_with("palette.") {
+                    return None;
+                }
+
+                let color = key
+                    .split('.')
+                    .try_fold(&value, |value, part| value.get(part))?
+                    .as_str()?;
+                if str_to_color(color).is_some() || self.palette.0.contains_key(color.trim()) {
+                    return None;
+                }
+
+                Some(ConfigProblem {
+                    message: format!("'{}' is neither a color nor a name in [palette]", color),
+                    key,
+                    line: None,
+                })
+            })
+            .collect()
+    }
+}
+
 impl PartialConfig {
-    pub fn from_file(path: &Path) -> io::Result<PartialConfig> {
+    /// Parse and validate the contents of a configuration file
+    ///
+    /// This never fails. The keys with a problem are left out, so the defaults are used instead.
+    pub fn parse(contents: &str) -> ParsedConfig {
+        Self::parse_with(contents, false)
+    }
+
+    /// Parse a theme, which only keeps the colors and modifiers
+    pub fn parse_theme(contents: &str) -> ParsedConfig {
+        Self::parse_with(contents, true)
+    }
+
+    fn parse_with(contents: &str, only_styles: bool) -> ParsedConfig {
+        let empty = || toml::from_str::<PartialConfig>("").ok();
+
+        let mut table = match toml::from_str::<Table>(contents) {
+            Ok(table) => table,
+            Err(err) => {
+                let problem = ConfigProblem {
+                    key: String::new(),
+                    line: err.line_col().map(|(line, _)| line + 1),
+                    message: format!("Invalid TOML. {}", err),
+                };
+
+                return ParsedConfig {
+                    partial: empty().unwrap_or_else(default_exit),
+                    keys: Vec::new(),
+                    problems: vec![problem],
+                    table: Table::new(),
+                };
+            }
+        };
+
+        let mut problems = Vec::new();
+        PartialConfig::validate_table(&mut table, "", &mut problems);
+        if only_styles {
+            retain_style_keys(&mut table, "", &mut problems);
+        }
+
+        for problem in problems.iter_mut() {
+            problem.line = find_key_line(contents, &problem.key);
+        }
+
+        let mut keys = Vec::new();
+        leaf_keys(&table, "", &mut keys);
+
+        let partial = Value::Table(table.clone())
+            .try_into()
+            .unwrap_or_else(|err| {
+                problems.push(ConfigProblem {
+                    key: String::new(),
+                    line: None,
+                    message: format!("Configuration cannot be used. {}", err),
+                });
+                empty().unwrap_or_else(default_exit)
+            });
+
+        ParsedConfig {
+            partial,
+            keys,
+            problems,
+            table,
+        }
+    }
+
+    pub fn from_file(path: &Path) -> io::Result<ParsedConfig> {
         let file = File::open(path)?;
         let mut buf_reader = BufReader::new(file);
         let mut contents = String::new();
         buf_reader.read_to_string(&mut contents)?;
-        Ok(toml::from_str(&contents).expect("Given configuration file contains errors."))
+        Ok(PartialConfig::parse(&contents))
+    }
+}
+
+fn default_exit<T>() -> T {
+    eprintln!("Empty configuration cannot be properly parsed");
+    process::exit(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_specs() {
+        let event = |code, modifiers| KeyEvent { code, modifiers };
+        let parse = |spec| KeySpec::parse(spec).unwrap();
+
+        assert!(parse("F1").matches(&event(KeyCode::F(1), KeyModifiers::NONE)));
+        assert!(parse("f12").matches(&event(KeyCode::F(12), KeyModifiers::NONE)));
+        assert!(parse("Ctrl-c").matches(&event(KeyCode::Char('c'), KeyModifiers::CONTROL)));
+        assert!(!parse("Ctrl-c").matches(&event(KeyCode::Char('c'), KeyModifiers::NONE)));
+        assert!(parse("Ctrl-C").matches(&event(KeyCode::Char('c'), KeyModifiers::CONTROL)));
+        assert!(parse("Alt-X").matches(&event(KeyCode::Char('x'), KeyModifiers::ALT)));
+        assert!(parse("Ctrl-Shift-c").matches(&event(
+            KeyCode::Char('C'),
+            KeyModifiers::CONTROL | KeyModifiers::SHIFT
+        )));
+        assert!(parse("Alt-Shift-Left").matches(&event(
+            KeyCode::Left,
+            KeyModifiers::ALT | KeyModifiers::SHIFT
+        )));
+        assert!(parse("Ctrl--").matches(&event(KeyCode::Char('-'), KeyModifiers::CONTROL)));
+        assert!(parse("Esc").matches(&event(KeyCode::Esc, KeyModifiers::NONE)));
+
+        // Shifted characters and tabs are reported differently by terminals
+        assert!(parse("Shift-a").matches(&event(KeyCode::Char('A'), KeyModifiers::SHIFT)));
+        assert!(parse("A").matches(&event(KeyCode::Char('A'), KeyModifiers::SHIFT)));
+        assert!(parse("A").matches(&event(KeyCode::Char('A'), KeyModifiers::NONE)));
+        assert!(!parse("a").matches(&event(KeyCode::Char('A'), KeyModifiers::SHIFT)));
+        assert!(parse("Shift-Tab").matches(&event(KeyCode::BackTab, KeyModifiers::SHIFT)));
+        assert!(!parse("Tab").matches(&event(KeyCode::BackTab, KeyModifiers::SHIFT)));
+
+        assert_eq!(KeySpec::parse("F25"), None);
+        assert_eq!(KeySpec::parse("Hyper-a"), None);
+        assert_eq!(KeySpec::parse("Ctrl-"), None);
+        assert_eq!(KeySpec::parse(""), None);
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(str_to_color("Light Red"), Some(Color::LightRed));
+        assert_eq!(str_to_color("reset"), Some(Color::Reset));
+        assert_eq!(str_to_color("#87CEEB"), Some(Color::Rgb(135, 206, 235)));
+        assert_eq!(str_to_color("#f80"), Some(Color::Rgb(255, 136, 0)));
+        assert_eq!(str_to_color("rgb(1, 2,3)"), Some(Color::Rgb(1, 2, 3)));
+        assert_eq!(str_to_color("244"), Some(Color::Indexed(244)));
+
+        assert_eq!(str_to_color("256"), None);
+        assert_eq!(str_to_color("#12345"), None);
+        assert_eq!(str_to_color("rgb(1, 2)"), None);
+        assert_eq!(str_to_color("rgb(1, 2, 3, 4)"), None);
+        assert_eq!(str_to_color("accent"), None);
+    }
+
+    #[test]
+    fn palette_names() {
+        let parsed = PartialConfig::parse(
+            "[palette]\naccent = \"#87CEEB\"\nbroken = \"#87CEE\"\n\n[power_controls]\nshutdown_hint_color = \"accent\"\nreboot_hint_color = \"missing\"\n\n[username_field.style]\ntitle_color = \"rgb(1, 2)\"\n",
+        );
+        let found: Vec<&str> = parsed.problems.iter().map(|p| &p.key[..]).collect();
+        assert_eq!(
+            found,
+            vec!["palette.broken", "username_field.style.title_color"]
+        );
+
+        let mut config = Config::default();
+        config.merge_in_partial(parsed.partial);
+        let found: Vec<String> = config
+            .check_palette_names()
+            .into_iter()
+            .map(|p| p.key)
+            .collect();
+        assert_eq!(found, vec!["power_controls.reboot_hint_color"]);
+    }
+
+    #[test]
+    fn builtin_themes() {
+        for (name, _) in BUILTIN_THEMES {
+            let (_, parsed) = load_theme(name).unwrap();
+            assert_eq!(parsed.problems, Vec::new(), "theme '{}'", name);
+
+            let mut config = Config::default();
+            config.merge_in_partial(parsed.partial);
+            assert_eq!(config.check_palette_names(), Vec::new(), "theme '{}'", name);
+        }
+
+        assert!(load_theme("../config").is_err());
+        assert!(load_theme("missing").is_err());
+    }
+
+    #[test]
+    fn theme_only_sets_styles() {
+        let parsed = PartialConfig::parse_theme(
+            "tty = 4\ncache_path = \"/tmp/cache\"\n\n[palette]\naccent = \"#87CEEB\"\n\n[power_controls]\nshutdown_cmd = \"rm -rf /\"\nshutdown_hint_color = \"accent\"\nshutdown_hint_modifiers = \"bold\"\n\n[username_field.style]\ntitle_color_focused = \"red\"\n",
+        );
+        let found: Vec<(&str, Option<usize>)> = parsed
+            .problems
+            .iter()
+            .map(|p| (&p.key[..], p.line))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("cache_path", Some(2)),
+                ("power_controls.shutdown_cmd", Some(8)),
+                ("tty", Some(1)),
+            ]
+        );
+        assert_eq!(
+            parsed.keys,
+            vec![
+                "palette.accent",
+                "power_controls.shutdown_hint_color",
+                "power_controls.shutdown_hint_modifiers",
+                "username_field.style.title_color_focused",
+            ]
+        );
+
+        let mut config = Config::default();
+        config.merge_in_partial(parsed.partial);
+        assert_eq!(config.tty, Config::default().tty);
+        assert_eq!(config.cache_path, Config::default().cache_path);
+        assert_eq!(
+            config.power_controls.shutdown_cmd,
+            Config::default().power_controls.shutdown_cmd
+        );
+        assert_eq!(config.power_controls.shutdown_hint_color, "accent");
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        let parsed = PartialConfig::parse(include_str!("../extra/config.toml"));
+        assert_eq!(parsed.problems, Vec::new());
+    }
+
+    #[test]
+    fn problems() {
+        let contents = r#"tty = 64
+pam_service = "login"
+colour = "red"
+
+[power_controls]
+shutdown_key = "F25"
+shutdown_hint_color = "rgb(not a color)"
+reboot_hint_modifiers = "bold, blinking"
+hint_margin = "wide"
+
+[environment.set]
+"VALID" = "yes"
+INVALID = 1
+"#;
+        let ParsedConfig {
+            partial,
+            keys,
+            problems,
+            ..
+        } = PartialConfig::parse(contents);
+
+        let found: Vec<(&str, Option<usize>)> = problems
+            .iter()
+            .map(|problem| (&problem.key[..], problem.line))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("colour", Some(3)),
+                ("environment.set.INVALID", Some(13)),
+                ("power_controls.hint_margin", Some(9)),
+                ("power_controls.reboot_hint_modifiers", Some(8)),
+                ("power_controls.shutdown_hint_color", Some(7)),
+                ("power_controls.shutdown_key", Some(6)),
+                ("tty", Some(1)),
+            ]
+        );
+
+        // Everything else is still used
+        assert_eq!(keys, vec!["environment.set.VALID", "pam_service"]);
+        assert_eq!(partial.tty, None);
+        assert_eq!(partial.pam_service.as_deref(), Some("login"));
+        let set = partial.environment.unwrap().set.unwrap();
+        assert_eq!(set.get("VALID").map(String::as_str), Some("yes"));
+        assert_eq!(set.get("INVALID"), None);
+    }
+
+    #[test]
+    fn invalid_toml() {
+        let parsed = PartialConfig::parse("tty = 2\n[power_controls\n");
+        assert_eq!(parsed.partial.tty, None);
+        assert_eq!(parsed.problems.len(), 1);
+        assert_eq!(parsed.problems[0].line, Some(2));
+    }
+
+    #[test]
+    fn dump() {
+        let mut config = Config::default();
+        let parsed = PartialConfig::parse("tty = 3\n[environment.set]\nVAR = \"value\"\n");
+        config.merge_in_partial(parsed.partial);
+
+        let origins = parsed
+            .keys
+            .into_iter()
+            .map(|key| (key, ConfigOrigin::file(Path::new("user.toml"))))
+            .collect();
+        let dumped = config.dump(Some(&origins)).unwrap();
+
+        assert!(dumped.contains("\ntty = 3 # user.toml\n"));
+        assert!(dumped.contains("\nVAR = \"value\" # user.toml\n"));
+        assert!(dumped.contains("\nshutdown_key = \"F1\" # default\n"));
+
+        // The dump is a configuration file itself
+        let reparsed = PartialConfig::parse(&config.dump(None).unwrap());
+        assert_eq!(reparsed.problems, Vec::new());
+        assert_eq!(reparsed.partial.tty, Some(3));
     }
 }
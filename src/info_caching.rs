@@ -1,24 +1,54 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, read_to_string, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
 use lazy_static::lazy_static;
 use log::{info, warn};
 use regex::Regex;
-use std::fs::{read_to_string, write};
+use serde::{Deserialize, Serialize};
 
-pub const CACHE_PATH: &str = "/var/cache/lemurs";
+const CACHE_VERSION: u32 = 1;
 const USERNAME_REGEX_STR: &str = r"^[a-z][-a-z0-9]*$";
 const USERNAME_LENGTH_LIMIT: usize = 32;
 
-// Saved in the /var/cache/lemurs file as
+// Saved in the cache file (by default /var/cache/lemurs) as
+// ```
+// version = 1
+// environment = "ENVIRONMENT"
+// username = "USERNAME"
+//
+// [user_environments]
+// USERNAME = "ENVIRONMENT"
+// ```
+//
+// Older versions of Lemurs saved it as
 // ```
 // ENVIRONMENT\n
 // USERNAME
 // ```
-#[derive(Debug, Clone)]
+// which is still read and replaced by the current format the next time the cache is set.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct CachedInfo {
+    /// A file without a version is from the first version that was written in this format
+    #[serde(default = "CachedInfo::first_version")]
+    version: u32,
+    /// The last environment that was used by anyone
     environment: Option<String>,
     username: Option<String>,
+    /// The last environment that was used by every user
+    #[serde(default)]
+    user_environments: BTreeMap<String, String>,
 }
 
 impl CachedInfo {
+    fn first_version() -> u32 {
+        1
+    }
+
     pub fn environment(&self) -> Option<&str> {
         self.environment.as_deref()
     }
@@ -26,79 +56,196 @@ impl CachedInfo {
     pub fn username(&self) -> Option<&str> {
         self.username.as_deref()
     }
+
+    /// The last environment that was used by every user
+    pub fn user_environments(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.user_environments
+            .iter()
+            .map(|(username, environment)| (username.as_str(), environment.as_str()))
+    }
+
+    /// The last environment that was used by a user
+    pub fn user_environment(&self, username: &str) -> Option<&str> {
+        self.user_environments.get(username).map(String::as_str)
+    }
+
+    /// Parse the contents of the cache file, which can also be in the format of older versions
+    fn parse(contents: &str) -> Self {
+        let mut cached = match toml::from_str::<CachedInfo>(contents) {
+            Ok(cached) => {
+                if cached.version > CACHE_VERSION {
+                    warn!(
+                        "Cache file has a newer version '{}' than supported. Trying to read it anyway.",
+                        cached.version
+                    );
+                }
+
+                cached
+            }
+            Err(_) => {
+                info!("Cache file is not in the current format. Reading it as the old format.");
+                Self::parse_legacy(contents)
+            }
+        };
+
+        if let Some(username) = cached.username.as_deref() {
+            if !is_valid_username(username) {
+                warn!("Cached username is not a valid username and is therefore not loaded.");
+                cached.username = None;
+            }
+        }
+
+        cached
+            .user_environments
+            .retain(|username, _| is_valid_username(username));
+
+        cached
+    }
+
+    /// Parse the format with two lines, which was used before the cache had a version
+    fn parse_legacy(contents: &str) -> Self {
+        // NOTE: The first line is empty when no environment was cached, so only the lines
+        // themselves are trimmed
+        let mut lines = contents.lines();
+
+        let non_empty = |line: Option<&str>| {
+            line.map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(str::to_string)
+        };
+
+        let environment = non_empty(lines.next());
+        let username = non_empty(lines.next());
+
+        Self {
+            version: CACHE_VERSION,
+            environment,
+            username,
+            user_environments: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CacheError {
+    UsernameTooLong,
+    InvalidUsername,
+    Serialize(toml::ser::Error),
+    Write(io::Error),
+}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CacheError::UsernameTooLong => write!(
+                f,
+                "Username is longer than {} characters",
+                USERNAME_LENGTH_LIMIT
+            ),
+            CacheError::InvalidUsername => {
+                write!(f, "Username does not match '{}'", USERNAME_REGEX_STR)
+            }
+            CacheError::Serialize(err) => write!(f, "Failed to serialize the cache. {}", err),
+            CacheError::Write(err) => write!(f, "Failed to write the cache file. {}", err),
+        }
+    }
 }
 
 lazy_static! {
     static ref USERNAME_REGEX: Regex = Regex::new(USERNAME_REGEX_STR).unwrap();
 }
 
-pub fn get_cached_information() -> CachedInfo {
+/// Check whether a username can be cached
+pub fn check_username(username: &str) -> Result<(), CacheError> {
+    // Username length check
+    if username.len() > USERNAME_LENGTH_LIMIT {
+        return Err(CacheError::UsernameTooLong);
+    }
+
+    // Username validity check (through regex)
+    if !USERNAME_REGEX.is_match(username) {
+        return Err(CacheError::InvalidUsername);
+    }
+
+    Ok(())
+}
+
+fn is_valid_username(username: &str) -> bool {
+    check_username(username)
+        .inspect_err(|err| warn!("{}", err))
+        .is_ok()
+}
+
+/// Write a file that only root can read without ever leaving it half written
+///
+/// The contents are first written to a temporary file next to it, which then replaces the file.
+pub fn write_atomically(path: &Path, contents: &str) -> io::Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Path has no file name"))?;
+
+    let mut temp_file_name = OsString::from(".");
+    temp_file_name.push(file_name);
+    temp_file_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_file_name);
+
+    // A temporary file that was left behind by a crash is replaced
+    let _ = fs::remove_file(&temp_path);
+
+    let result = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&temp_path, path));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+
+    result
+}
+
+pub fn get_cached_information(cache_path: &str) -> CachedInfo {
     info!(
         "Attempting to get a cached information from '{}'",
-        CACHE_PATH
+        cache_path
     );
 
-    match read_to_string(CACHE_PATH) {
+    match read_to_string(cache_path) {
         Ok(cached) => {
-            // Remove any line feeds
-            let cached = cached.trim().to_string();
-
-            let mut lines = cached.lines();
-
-            let cached_environment = lines.next();
-            let cached_username = lines.next();
+            let cached = CachedInfo::parse(&cached);
 
             info!(
                 "Read cache file and found environment '{}' and username '{}'",
-                cached_environment.unwrap_or("None"),
-                cached_username.unwrap_or("None")
+                cached.environment().unwrap_or("None"),
+                cached.username().unwrap_or("None")
             );
 
-            let cached_username = if let Some(cached_username) = cached_username {
-                // Username length check
-                if cached_username.len() > USERNAME_LENGTH_LIMIT {
-                    warn!("Cached username is too long and is therefore not loaded.");
-                    None
-
-                // Username validity check (through regex)
-                } else if !USERNAME_REGEX.is_match(cached_username) {
-                    warn!("Cached username is not a valid username and is therefore not loaded.");
-                    None
-                } else {
-                    Some(cached_username)
-                }
-            } else {
-                cached_username
-            };
-
-            CachedInfo {
-                environment: cached_environment.map(|x| x.to_string()),
-                username: cached_username.map(|x| x.to_string()),
-            }
+            cached
         }
         Err(err) => {
             warn!("Unable to read cache file. Reason: '{}'", err);
-            CachedInfo {
-                environment: None,
-                username: None,
-            }
+            CachedInfo::default()
         }
     }
 }
 
-pub fn set_cache(environment: Option<&str>, username: Option<&str>) {
+fn write_cache(cache_path: &str, cached: &CachedInfo) -> Result<(), CacheError> {
+    let cache_file_content = toml::to_string(cached).map_err(CacheError::Serialize)?;
+    write_atomically(Path::new(cache_path), &cache_file_content).map_err(CacheError::Write)
+}
+
+pub fn set_cache(cache_path: &str, environment: Option<&str>, username: Option<&str>) {
     info!("Attempting to set cache");
 
     let username = if let Some(username) = username {
-        // Username length check
-        if username.len() > USERNAME_LENGTH_LIMIT {
-            warn!("Username is too long and is therefore not cached.");
-            return;
-        }
-
-        // Username validity check (through regex)
-        if !USERNAME_REGEX.is_match(username) {
-            warn!("Username is not a valid username and is therefore not cached.");
+        if !is_valid_username(username) {
+            warn!("Username is therefore not cached.");
             None
         } else {
             Some(username)
@@ -107,13 +254,19 @@ pub fn set_cache(environment: Option<&str>, username: Option<&str>) {
         None
     };
 
-    let cache_file_content = format!(
-        "{}\n{}\n",
-        environment.unwrap_or_default(),
-        username.unwrap_or_default()
-    );
+    // Keep the environments of the other users
+    let mut cached = get_cached_information(cache_path);
+    cached.version = CACHE_VERSION;
+    cached.environment = environment.map(str::to_string);
+    cached.username = username.map(str::to_string);
+
+    if let (Some(environment), Some(username)) = (environment, username) {
+        cached
+            .user_environments
+            .insert(username.to_string(), environment.to_string());
+    }
 
-    match write(CACHE_PATH, cache_file_content) {
+    match write_cache(cache_path, &cached) {
         Err(err) => {
             warn!("Failed to set username to cache file. Reason: '{}'", err);
         }
@@ -122,3 +275,154 @@ pub fn set_cache(environment: Option<&str>, username: Option<&str>) {
         }
     }
 }
+
+/// Change only the given values in the cache
+///
+/// In contrast to `set_cache`, an invalid username is an error. When both values are given, the
+/// environment is also remembered for that user.
+pub fn update_cache(
+    cache_path: &str,
+    environment: Option<&str>,
+    username: Option<&str>,
+) -> Result<(), CacheError> {
+    if let Some(username) = username {
+        check_username(username)?;
+    }
+
+    let mut cached = get_cached_information(cache_path);
+    cached.version = CACHE_VERSION;
+
+    if let Some(environment) = environment {
+        cached.environment = Some(environment.to_string());
+    }
+    if let Some(username) = username {
+        cached.username = Some(username.to_string());
+
+        if let Some(environment) = environment {
+            cached
+                .user_environments
+                .insert(username.to_string(), environment.to_string());
+        }
+    }
+
+    write_cache(cache_path, &cached)
+}
+
+/// Remove all the cached information
+pub fn clear_cache(cache_path: &str) -> Result<(), CacheError> {
+    match fs::remove_file(cache_path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(CacheError::Write(err)),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn current_format() {
+        let cached = CachedInfo::parse(
+            "version = 1\nenvironment = \"Sway\"\nusername = \"lemur\"\n\n[user_environments]\nlemur = \"Sway\"\nroot = \"TTYSHELL\"\n\"Not Valid\" = \"i3\"\n",
+        );
+
+        assert_eq!(cached.environment(), Some("Sway"));
+        assert_eq!(cached.username(), Some("lemur"));
+        assert_eq!(cached.user_environment("lemur"), Some("Sway"));
+        assert_eq!(cached.user_environment("root"), Some("TTYSHELL"));
+        assert_eq!(cached.user_environment("Not Valid"), None);
+        assert_eq!(cached.user_environment("other"), None);
+
+        let reparsed = CachedInfo::parse(&toml::to_string(&cached).unwrap());
+        assert_eq!(reparsed.environment(), Some("Sway"));
+        assert_eq!(reparsed.username(), Some("lemur"));
+        assert_eq!(reparsed.user_environments, cached.user_environments);
+
+        // A file without a version is still read as the current format
+        let cached =
+            CachedInfo::parse("username = \"lemur\"\n\n[user_environments]\nlemur = \"i3\"\n");
+        assert_eq!(cached.version, 1);
+        assert_eq!(cached.environment(), None);
+        assert_eq!(cached.username(), Some("lemur"));
+        assert_eq!(cached.user_environment("lemur"), Some("i3"));
+    }
+
+    #[test]
+    fn legacy_format() {
+        let cached = CachedInfo::parse("i3\nlemur\n");
+        assert_eq!(cached.version, CACHE_VERSION);
+        assert_eq!(cached.environment(), Some("i3"));
+        assert_eq!(cached.username(), Some("lemur"));
+        assert!(cached.user_environments.is_empty());
+
+        let cached = CachedInfo::parse("\nlemur\n");
+        assert_eq!(cached.environment(), None);
+        assert_eq!(cached.username(), Some("lemur"));
+
+        let cached = CachedInfo::parse("i3\nNot A Username\n");
+        assert_eq!(cached.environment(), Some("i3"));
+        assert_eq!(cached.username(), None);
+
+        let cached = CachedInfo::parse("");
+        assert_eq!(cached.environment(), None);
+        assert_eq!(cached.username(), None);
+    }
+
+    #[test]
+    fn atomic_writes() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("lemurs-cache-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("cache");
+
+        // Even an existing file with other permissions is replaced by a private file
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        fs::write(dir.join(".cache.tmp"), "left behind").unwrap();
+
+        write_atomically(&path, "new").unwrap();
+
+        assert_eq!(read_to_string(&path).unwrap(), "new");
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+        assert!(!dir.join(".cache.tmp").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn update_and_clear() {
+        let dir = std::env::temp_dir().join(format!("lemurs-update-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("cache");
+        let path = path.to_str().unwrap();
+
+        update_cache(path, Some("i3"), None).unwrap();
+        update_cache(path, None, Some("lemur")).unwrap();
+        let cached = get_cached_information(path);
+        assert_eq!(cached.environment(), Some("i3"));
+        assert_eq!(cached.username(), Some("lemur"));
+        assert_eq!(cached.user_environment("lemur"), None);
+
+        update_cache(path, Some("Sway"), Some("lemur")).unwrap();
+        assert_eq!(
+            get_cached_information(path).user_environment("lemur"),
+            Some("Sway")
+        );
+
+        assert!(matches!(
+            update_cache(path, None, Some("Not Valid")),
+            Err(CacheError::InvalidUsername)
+        ));
+        assert_eq!(get_cached_information(path).username(), Some("lemur"));
+
+        clear_cache(path).unwrap();
+        clear_cache(path).unwrap();
+        assert_eq!(get_cached_information(path).username(), None);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
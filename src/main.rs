@@ -1,4 +1,6 @@
+use std::collections::BTreeMap;
 use std::error::Error;
+use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
 use std::process;
@@ -19,42 +21,196 @@ mod post_login;
 mod ui;
 
 use auth::{try_auth, AuthUserInfo};
-use config::Config;
+use config::{Config, ConfigOrigin, ConfigProblem, ParsedConfig};
 use post_login::{EnvironmentStartError, PostLoginEnvironment};
 
 const DEFAULT_CONFIG_PATH: &str = "/etc/lemurs/config.toml";
+const DEFAULT_CONFIG_DROP_IN_DIR: &str = "/etc/lemurs/config.d";
 const PREVIEW_LOG_PATH: &str = "lemurs.log";
 const DEFAULT_LOG_PATH: &str = "/var/log/lemurs.log";
+const PREVIEW_CACHE_PATH: &str = "lemurs.cache";
+const PREVIEW_FAILED_LOGINS_PATH: &str = "lemurs-failed-logins";
+
+/// What was loaded from the configuration files
+#[derive(Default)]
+struct ConfigReport {
+    /// The configuration files that were loaded in the order they were merged in. The theme is
+    /// not one of them.
+    files: Vec<String>,
+    /// The problems within every file. The defaults are used for the keys with a problem.
+    problems: Vec<(String, ConfigProblem)>,
+    /// The file that last set every dotted key path which is not the default
+    origins: BTreeMap<String, ConfigOrigin>,
+}
+
+impl ConfigReport {
+    fn merge_in(&mut self, config: &mut Config, origin: ConfigOrigin, parsed: ParsedConfig) {
+        config.merge_in_partial(parsed.partial);
 
-fn merge_in_configuration(config: &mut Config, config_path: Option<&Path>) {
-    let load_config_path = config_path.unwrap_or_else(|| Path::new(DEFAULT_CONFIG_PATH));
+        self.problems.extend(
+            parsed
+                .problems
+                .into_iter()
+                .map(|problem| (origin.label.clone(), problem)),
+        );
+        for key in parsed.keys {
+            self.origins.insert(key, origin.clone());
+        }
+    }
+
+    /// Report the colors that are not in the merged palette in the file that set them
+    fn check_palette_names(&mut self, config: &Config) {
+        for mut problem in config.check_palette_names() {
+            let origin = self
+                .origins
+                .get(&problem.key)
+                .cloned()
+                .unwrap_or_else(|| ConfigOrigin::other("default"));
+
+            problem.line = origin.find_key_line(&problem.key);
+            self.problems.push((origin.label, problem));
+        }
+    }
+}
 
-    match config::PartialConfig::from_file(load_config_path) {
-        Ok(partial_config) => {
+/// The drop-in configuration files in a directory in lexical order
+fn drop_in_files(drop_in_dir: &Path) -> Vec<PathBuf> {
+    let mut files = match fs::read_dir(drop_in_dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "toml"))
+            .collect::<Vec<_>>(),
+        Err(err) => {
             info!(
-                "Successfully loaded configuration file from '{}'",
-                load_config_path.display()
+                "No drop-in configuration files loaded from '{}'. Reason: {}",
+                drop_in_dir.display(),
+                err
             );
-            config.merge_in_partial(partial_config)
+            Vec::new()
         }
-        Err(err) => {
-            // If we have given it a specific config path, it should crash if this file cannot be
-            // loaded. If it is the default config location just put a warning in the logs.
-            if let Some(config_path) = config_path {
+    };
+
+    files.sort();
+    files
+}
+
+fn load_configuration_file(path: &Path) -> io::Result<(ConfigOrigin, ParsedConfig)> {
+    let parsed = config::PartialConfig::from_file(path)?;
+    let origin = ConfigOrigin::file(path);
+
+    info!("Successfully loaded configuration file from '{}'", origin);
+    Ok((origin, parsed))
+}
+
+/// Load the configuration files in the order in which they are merged in
+///
+/// A given `config_path` replaces both the default configuration file and the drop-in files.
+fn load_configuration_files(
+    config_path: Option<&Path>,
+    default_config_path: &Path,
+    drop_in_dir: &Path,
+) -> Vec<(ConfigOrigin, ParsedConfig)> {
+    let mut files = Vec::new();
+
+    if let Some(config_path) = config_path {
+        // If we have given it a specific config path, it should crash if this file cannot be
+        // loaded.
+        match load_configuration_file(config_path) {
+            Ok(file) => files.push(file),
+            Err(err) => {
                 eprintln!(
                     "The config file '{}' cannot be loaded.\nReason: {}",
                     config_path.display(),
                     err
                 );
                 process::exit(1);
-            } else {
-                warn!(
-                    "No configuration file loaded from the expected location ({}). Reason: {}",
-                    DEFAULT_CONFIG_PATH, err
-                );
             }
         }
+    } else {
+        // If it is the default config location just put a warning in the logs.
+        match load_configuration_file(default_config_path) {
+            Ok(file) => files.push(file),
+            Err(err) => warn!(
+                "No configuration file loaded from the expected location ({}). Reason: {}",
+                default_config_path.display(),
+                err
+            ),
+        }
+
+        for path in drop_in_files(drop_in_dir) {
+            match load_configuration_file(&path) {
+                Ok(file) => files.push(file),
+                Err(err) => warn!(
+                    "The drop-in configuration file '{}' cannot be loaded. Reason: {}",
+                    path.display(),
+                    err
+                ),
+            }
+        }
+    }
+
+    files
+}
+
+/// Merge the theme and the configuration files into the defaults
+///
+/// A given `theme` replaces the theme of the configuration files.
+fn merge_in_configuration(
+    config: &mut Config,
+    files: Vec<(ConfigOrigin, ParsedConfig)>,
+    theme: Option<&str>,
+) -> ConfigReport {
+    let mut report = ConfigReport::default();
+
+    // The theme goes in between the defaults and the configuration files, so that the keys of
+    // the files override it
+    let theme_from_flag = theme.is_some();
+    let (theme, theme_origin) = match theme {
+        Some(theme) => (theme.to_string(), ConfigOrigin::other("--theme")),
+        None => files
+            .iter()
+            .rev()
+            .find_map(|(origin, parsed)| Some((parsed.partial.theme.clone()?, origin.clone())))
+            .unwrap_or_else(|| (config.theme.clone(), ConfigOrigin::other("default"))),
+    };
+
+    let theme_loaded = match config::load_theme(&theme) {
+        Ok((origin, parsed)) => {
+            info!("Loaded the theme '{}' from '{}'", theme, origin);
+            report.merge_in(config, origin, parsed);
+            true
+        }
+        Err(message) => {
+            let line = theme_origin.find_key_line("theme");
+            report.problems.push((
+                theme_origin.label.clone(),
+                ConfigProblem {
+                    key: String::from("theme"),
+                    line,
+                    message,
+                },
+            ));
+            false
+        }
+    };
+
+    // Styles that a file leaves at their default do not override the theme
+    let defaults = Config::default();
+    for (origin, parsed) in files {
+        let parsed = if theme_loaded {
+            parsed.without_default_styles(&defaults)
+        } else {
+            parsed
+        };
+        report.files.push(origin.label.clone());
+        report.merge_in(config, origin, parsed);
     }
+    if theme_from_flag {
+        report.origins.insert(String::from("theme"), theme_origin);
+    }
+    config.theme = theme;
+
+    report
 }
 
 fn setup_logger(is_preview: bool) {
@@ -105,21 +261,171 @@ struct Cli {
     no_log: bool,
 
     /// Override the configured TTY number
-    #[clap(long, value_name = "N")]
+    #[clap(long, value_name = "N", value_parser = parse_tty)]
     tty: Option<u8>,
 
     /// A file to replace the default configuration
     #[clap(short, long, value_name = "FILE")]
     config: Option<PathBuf>,
 
+    /// Override the configured theme
+    #[clap(long, value_name = "NAME")]
+    theme: Option<String>,
+
     #[clap(subcommand)]
     command: Option<Commands>,
 }
 
+fn parse_tty(value: &str) -> Result<u8, String> {
+    let tty = value.parse::<i64>().map_err(|err| err.to_string())?;
+    config::check_tty(tty)?;
+
+    // The range of TTYs fits within a `u8`
+    Ok(tty as u8)
+}
+
 #[derive(Subcommand)]
 enum Commands {
     Envs,
-    Cache,
+    /// Show or change the cached environment and username
+    Cache {
+        #[clap(subcommand)]
+        command: Option<CacheCommands>,
+    },
+    /// Inspect the configuration file
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Report all problems within the configuration file
+    Check,
+    /// Print the configuration that is used after merging the file into the defaults
+    Dump {
+        /// Annotate every value with the file that set it or with `default`
+        #[clap(long)]
+        origins: bool,
+    },
+}
+
+fn check_config(report: &ConfigReport) {
+    if report.files.is_empty() {
+        eprintln!(
+            "No configuration file could be loaded from '{}'",
+            DEFAULT_CONFIG_PATH
+        );
+        process::exit(1);
+    }
+
+    if report.problems.is_empty() {
+        println!("No problems found in {}", report.files.join(", "));
+        return;
+    }
+
+    for (file, problem) in &report.problems {
+        eprintln!("{}: {}", file, problem);
+    }
+    eprintln!("Found {} problem(s).", report.problems.len());
+    process::exit(1);
+}
+
+#[derive(Subcommand)]
+enum CacheCommands {
+    /// Show the cached values (default)
+    Show {
+        /// Print the cached values as JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// Set the cached values. Giving both also sets the environment of that user.
+    Set {
+        #[clap(long, value_name = "USERNAME")]
+        user: Option<String>,
+
+        #[clap(long, value_name = "ENVIRONMENT")]
+        env: Option<String>,
+
+        /// Also set an environment that is not currently available
+        #[clap(long)]
+        force: bool,
+    },
+    /// Remove all cached values
+    Clear,
+}
+
+fn show_cache(cache_path: &str, json: bool) {
+    let cached_info = info_caching::get_cached_information(cache_path);
+
+    if json {
+        match serde_json::to_string_pretty(&cached_info) {
+            Ok(json) => println!("{}", json),
+            Err(err) => {
+                eprintln!("Failed to export the cache as JSON. Reason: {}", err);
+                process::exit(1);
+            }
+        }
+
+        return;
+    }
+
+    let environment = cached_info
+        .environment()
+        .map(|s| format!("'{}'", s))
+        .unwrap_or_else(|| String::from("No cached value"));
+    let username = cached_info
+        .username()
+        .map(|s| format!("'{}'", s))
+        .unwrap_or_else(|| String::from("No cached value"));
+
+    println!("Information currently cached within '{}'\n", cache_path);
+
+    println!("environment: {}", environment);
+    println!("username: {}", username);
+
+    for (username, environment) in cached_info.user_environments() {
+        println!("environment of '{}': '{}'", username, environment);
+    }
+}
+
+fn run_cache_command(config: &Config, command: CacheCommands) {
+    let cache_path = &config.cache_path;
+
+    match command {
+        CacheCommands::Show { json } => show_cache(cache_path, json),
+        CacheCommands::Set { user, env, force } => {
+            if user.is_none() && env.is_none() {
+                eprintln!("Nothing to set. Give at least one of --user and --env.");
+                process::exit(2);
+            }
+
+            if let Some(env) = env.as_deref() {
+                let envs = post_login::get_envs(config);
+                if !force && !envs.iter().any(|(env_name, _)| env_name == env) {
+                    eprintln!(
+                        "'{}' is not one of the currently available environments. Give --force to set it anyway.",
+                        env
+                    );
+                    process::exit(1);
+                }
+            }
+
+            if let Err(err) =
+                info_caching::update_cache(cache_path, env.as_deref(), user.as_deref())
+            {
+                eprintln!("Failed to set the cache. Reason: {}", err);
+                process::exit(1);
+            }
+        }
+        CacheCommands::Clear => {
+            if let Err(err) = info_caching::clear_cache(cache_path) {
+                eprintln!("Failed to clear the cache. Reason: {}", err);
+                process::exit(1);
+            }
+        }
+    }
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
@@ -127,36 +433,41 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     // Load and setup configuration
     let mut config = Config::default();
-    merge_in_configuration(&mut config, cli.config.as_deref());
+    let config_files = load_configuration_files(
+        cli.config.as_deref(),
+        Path::new(DEFAULT_CONFIG_PATH),
+        Path::new(DEFAULT_CONFIG_DROP_IN_DIR),
+    );
+    let mut config_report = merge_in_configuration(&mut config, config_files, cli.theme.as_deref());
+    config_report.check_palette_names(&config);
+    config::set_palette(&config.palette);
 
     if let Some(cmd) = cli.command {
         match cmd {
             Commands::Envs => {
-                let envs = post_login::get_envs(config.environment_switcher.include_tty_shell);
+                let envs = post_login::get_envs(&config);
 
                 for (env_name, _) in envs.into_iter() {
                     println!("{}", env_name);
                 }
             }
-            Commands::Cache => {
-                let cached_info = info_caching::get_cached_information();
-
-                let environment = cached_info
-                    .environment()
-                    .map(|s| format!("'{}'", s))
-                    .unwrap_or_else(|| String::from("No cached value"));
-                let username = cached_info
-                    .username()
-                    .map(|s| format!("'{}'", s))
-                    .unwrap_or_else(|| String::from("No cached value"));
-
-                println!(
-                    "Information currently cached within '{}'\n",
-                    info_caching::CACHE_PATH
+            Commands::Config { command } => match command {
+                ConfigCommands::Check => check_config(&config_report),
+                ConfigCommands::Dump { origins } => {
+                    match config.dump(origins.then_some(&config_report.origins)) {
+                        Ok(dumped) => print!("{}", dumped),
+                        Err(err) => {
+                            eprintln!("Failed to serialize the configuration. Reason: {}", err);
+                            process::exit(1);
+                        }
+                    }
+                }
+            },
+            Commands::Cache { command } => {
+                run_cache_command(
+                    &config,
+                    command.unwrap_or(CacheCommands::Show { json: false }),
                 );
-
-                println!("environment: {}", environment);
-                println!("username: {}", username);
             }
         }
 
@@ -170,12 +481,28 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     info!("Lemurs logger is running");
 
+    for (file, problem) in &config_report.problems {
+        warn!("Problem in the configuration file '{}'. {}", file, problem);
+    }
+    for (key, file) in &config_report.origins {
+        info!("Configuration key '{}' is set by '{}'", key, file);
+    }
+
     if let Some(tty) = cli.tty {
         info!("Overwritten the tty to '{}' with the --tty flag", tty);
         config.tty = tty;
     }
 
-    if !cli.preview {
+    if cli.preview {
+        // Don't touch the cache of the system when previewing
+        info!(
+            "Using the cache at '{}' for the preview",
+            PREVIEW_CACHE_PATH
+        );
+        config.cache_path = PREVIEW_CACHE_PATH.to_string();
+        config.authentication.throttling.failed_logins_path =
+            PREVIEW_FAILED_LOGINS_PATH.to_string();
+    } else {
         // Switch to the proper tty
         info!("Switching to tty {}", config.tty);
 
@@ -224,3 +551,109 @@ fn post_login_env_start<'a>(
 ) -> Result<(), EnvironmentStartError> {
     post_login_env.start(config, user_info)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drop_in_configuration() {
+        let dir = std::env::temp_dir().join(format!("lemurs-config-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let drop_in_dir = dir.join("config.d");
+        fs::create_dir_all(&drop_in_dir).unwrap();
+
+        let config_path = dir.join("config.toml");
+        fs::write(&config_path, "tty = 3\npam_service = \"base\"\n").unwrap();
+        // Written in the reverse order to not depend on the order of the directory entries
+        fs::write(drop_in_dir.join("20-b.toml"), "tty = 5\n").unwrap();
+        fs::write(
+            drop_in_dir.join("10-a.toml"),
+            "tty = 4\npam_service = \"a\"\n",
+        )
+        .unwrap();
+        fs::write(drop_in_dir.join("30-c.toml.bak"), "tty = 6\n").unwrap();
+
+        let mut config = Config::default();
+        let files = load_configuration_files(None, &config_path, &drop_in_dir);
+        let report = merge_in_configuration(&mut config, files, None);
+
+        // The drop-in files are merged in lexical order after the configuration file
+        assert_eq!(
+            report.files,
+            [
+                config_path.display().to_string(),
+                drop_in_dir.join("10-a.toml").display().to_string(),
+                drop_in_dir.join("20-b.toml").display().to_string(),
+            ]
+        );
+        assert_eq!(config.tty, 5);
+        assert_eq!(config.pam_service, "a");
+        assert_eq!(
+            report.origins.get("tty"),
+            Some(&ConfigOrigin::file(&drop_in_dir.join("20-b.toml")))
+        );
+        assert_eq!(
+            report.origins.get("pam_service"),
+            Some(&ConfigOrigin::file(&drop_in_dir.join("10-a.toml")))
+        );
+
+        // A given configuration file replaces the configuration file and the drop-in files
+        let given_path = dir.join("given.toml");
+        fs::write(&given_path, "tty = 7\n").unwrap();
+
+        let mut config = Config::default();
+        let files = load_configuration_files(Some(&given_path), &config_path, &drop_in_dir);
+        let report = merge_in_configuration(&mut config, files, None);
+
+        assert_eq!(report.files, [given_path.display().to_string()]);
+        assert_eq!(config.tty, 7);
+        assert_eq!(config.pam_service, Config::default().pam_service);
+        assert_eq!(
+            report.origins.get("tty"),
+            Some(&ConfigOrigin::file(&given_path))
+        );
+        assert_eq!(report.origins.get("pam_service"), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn theme_over_installed_configuration() {
+        // The installed configuration file sets every style to its default
+        let installed = config::PartialConfig::parse(include_str!("../extra/config.toml"));
+        let changed =
+            config::PartialConfig::parse("[power_controls]\nreboot_hint_color = \"red\"\n");
+        let files = vec![
+            (ConfigOrigin::other("config.toml"), installed),
+            (ConfigOrigin::other("changed.toml"), changed),
+        ];
+
+        let mut config = Config::default();
+        let report = merge_in_configuration(&mut config, files, Some("solarized"));
+
+        let theme = ConfigOrigin::other("built-in theme 'solarized'");
+        let style = &config.username_field.style;
+        assert_eq!(style.title_color_focused, "solarized-blue");
+        assert_eq!(
+            report
+                .origins
+                .get("username_field.style.title_color_focused"),
+            Some(&theme)
+        );
+        assert_eq!(config.power_controls.shutdown_hint_color, "base01");
+
+        // A style that differs from the default still overrides the theme
+        assert_eq!(config.power_controls.reboot_hint_color, "red");
+        assert_eq!(
+            report.origins.get("power_controls.reboot_hint_color"),
+            Some(&ConfigOrigin::other("changed.toml"))
+        );
+
+        // Other keys are still set by the file
+        assert_eq!(
+            report.origins.get("tty"),
+            Some(&ConfigOrigin::other("config.toml"))
+        );
+    }
+}
@@ -0,0 +1,273 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use log::{info, warn};
+
+const DESKTOP_ENTRY_GROUP: &str = "[Desktop Entry]";
+const DESKTOP_FILE_EXTENSION: &str = "desktop";
+const FALLBACK_SEARCH_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/bin";
+
+/// A session described by a freedesktop `.desktop` file
+///
+/// See https://specifications.freedesktop.org/desktop-entry-spec/latest/ for the format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DesktopSession {
+    /// The `Name` of the session, which is shown in the switcher
+    pub name: String,
+    /// The `Exec` command with all field codes removed
+    pub exec: String,
+    /// The `DesktopNames` joined with `:`, as expected by `XDG_CURRENT_DESKTOP`
+    pub desktop_names: Option<String>,
+}
+
+/// Parse the contents of a session `.desktop` file
+///
+/// Returns `None` if the session should not be shown. This is the case when it is marked as
+/// `Hidden` or `NoDisplay`, when it lacks a `Name` or `Exec` or when the `TryExec` binary cannot
+/// be found.
+pub fn parse_desktop_file(contents: &str) -> Option<DesktopSession> {
+    let mut in_desktop_entry = false;
+
+    let mut name = None;
+    let mut exec = None;
+    let mut try_exec = None;
+    let mut desktop_names = None;
+
+    for line in contents.lines() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            in_desktop_entry = line == DESKTOP_ENTRY_GROUP;
+            continue;
+        }
+
+        if !in_desktop_entry {
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+
+        // NOTE: Localized keys (e.g. `Name[nl]`) are not equal to any of these and are therefore
+        // ignored.
+        match key.trim() {
+            "Name" => name = Some(value.trim().to_string()),
+            "Exec" => exec = Some(value.trim().to_string()),
+            "TryExec" => try_exec = Some(value.trim().to_string()),
+            "DesktopNames" => desktop_names = Some(value.trim().to_string()),
+            "Hidden" | "NoDisplay" if value.trim() == "true" => return None,
+            _ => {}
+        }
+    }
+
+    let name = name.filter(|name| !name.is_empty())?;
+    let exec = exec.map(|exec| strip_field_codes(&exec))?;
+
+    if exec.is_empty() {
+        return None;
+    }
+
+    if let Some(try_exec) = try_exec {
+        if !is_executable_available(&try_exec) {
+            info!(
+                "Skipping session '{}' since its TryExec '{}' was not found",
+                name, try_exec
+            );
+            return None;
+        }
+    }
+
+    let desktop_names = desktop_names
+        .map(|names| {
+            names
+                .split(';')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .collect::<Vec<_>>()
+                .join(":")
+        })
+        .filter(|names| !names.is_empty());
+
+    Some(DesktopSession {
+        name,
+        exec,
+        desktop_names,
+    })
+}
+
+/// Get all the sessions from the `.desktop` files in a directory
+///
+/// The sessions are sorted by their file name.
+pub fn get_desktop_sessions(dir: &Path) -> Vec<DesktopSession> {
+    let paths = match fs::read_dir(dir) {
+        Ok(paths) => paths,
+        Err(err) => {
+            info!(
+                "Failed to read from the session folder '{}'. Reason: {}",
+                dir.display(),
+                err
+            );
+            return Vec::new();
+        }
+    };
+
+    let mut paths: Vec<_> = paths
+        .filter_map(|path| match path {
+            Ok(path) => Some(path.path()),
+            Err(err) => {
+                warn!("Ignored errorinous path: '{}'", err);
+                None
+            }
+        })
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| ext == DESKTOP_FILE_EXTENSION)
+        })
+        .collect();
+    paths.sort();
+
+    paths
+        .into_iter()
+        .filter_map(|path| match fs::read_to_string(&path) {
+            Ok(contents) => parse_desktop_file(&contents),
+            Err(err) => {
+                warn!(
+                    "Failed to read session file '{}'. Reason: {}",
+                    path.display(),
+                    err
+                );
+                None
+            }
+        })
+        .collect()
+}
+
+/// Remove the field codes (e.g. `%f` or `%U`) from an `Exec` value
+fn strip_field_codes(exec: &str) -> String {
+    exec.split_whitespace()
+        .filter_map(|arg| {
+            if arg.len() == 2 && arg.starts_with('%') && arg != "%%" {
+                None
+            } else {
+                Some(arg.replace("%%", "%"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Check whether a `TryExec` binary exists, either as an absolute path or within the `PATH`
+fn is_executable_available(binary: &str) -> bool {
+    fn is_executable(path: &Path) -> bool {
+        fs::metadata(path)
+            .map(|metadata| {
+                metadata.is_file() && std::os::unix::fs::MetadataExt::mode(&metadata) & 0o111 != 0
+            })
+            .unwrap_or(false)
+    }
+
+    if binary.contains('/') {
+        return is_executable(Path::new(binary));
+    }
+
+    let search_path = env::var("PATH").unwrap_or_else(|_| FALLBACK_SEARCH_PATH.to_string());
+    search_path
+        .split(':')
+        .any(|dir| is_executable(&Path::new(dir).join(binary)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn basic_session() {
+        let session = parse_desktop_file(
+            "[Desktop Entry]\nName=Sway\nComment=An i3-compatible Wayland compositor\nExec=sway\nType=Application\nDesktopNames=sway\n",
+        );
+
+        assert_eq!(
+            session,
+            Some(DesktopSession {
+                name: "Sway".to_string(),
+                exec: "sway".to_string(),
+                desktop_names: Some("sway".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn hidden_sessions() {
+        assert_eq!(
+            parse_desktop_file("[Desktop Entry]\nName=A\nExec=a\nHidden=true\n"),
+            None
+        );
+        assert_eq!(
+            parse_desktop_file("[Desktop Entry]\nName=A\nExec=a\nNoDisplay=true\n"),
+            None
+        );
+        assert!(parse_desktop_file("[Desktop Entry]\nName=A\nExec=a\nNoDisplay=false\n").is_some());
+    }
+
+    #[test]
+    fn missing_keys() {
+        assert_eq!(parse_desktop_file("[Desktop Entry]\nExec=a\n"), None);
+        assert_eq!(parse_desktop_file("[Desktop Entry]\nName=A\n"), None);
+        assert_eq!(parse_desktop_file("Name=A\nExec=a\n"), None);
+    }
+
+    #[test]
+    fn localized_and_other_groups() {
+        let session = parse_desktop_file(
+            "# Comment\n[Desktop Entry]\nName[nl]=Plasma (NL)\nName=Plasma\nExec=startplasma-x11\n\n[Desktop Action Foo]\nName=Foo\nExec=foo\n",
+        )
+        .unwrap();
+
+        assert_eq!(session.name, "Plasma");
+        assert_eq!(session.exec, "startplasma-x11");
+        assert_eq!(session.desktop_names, None);
+    }
+
+    #[test]
+    fn desktop_names() {
+        let session = parse_desktop_file(
+            "[Desktop Entry]\nName=GNOME\nExec=gnome-session\nDesktopNames=GNOME;GNOME-Classic;\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            session.desktop_names.as_deref(),
+            Some("GNOME:GNOME-Classic")
+        );
+    }
+
+    #[test]
+    fn field_codes() {
+        assert_eq!(
+            strip_field_codes("env FOO=1 wm %U --flag"),
+            "env FOO=1 wm --flag"
+        );
+        assert_eq!(strip_field_codes("printf 100%%"), "printf 100%");
+    }
+
+    #[test]
+    fn try_exec() {
+        assert!(parse_desktop_file("[Desktop Entry]\nName=A\nExec=a\nTryExec=/bin/sh\n").is_some());
+        assert_eq!(
+            parse_desktop_file(
+                "[Desktop Entry]\nName=A\nExec=a\nTryExec=/nonexistent/lemurs-test-binary\n"
+            ),
+            None
+        );
+        assert_eq!(
+            parse_desktop_file("[Desktop Entry]\nName=A\nExec=a\nTryExec=lemurs-test-binary\n"),
+            None
+        );
+    }
+}
@@ -1,65 +1,225 @@
-use log::{error, info};
-use std::env;
+use log::{info, warn};
+use std::collections::BTreeMap;
 
-fn env_set_and_announce(key: &str, value: &str) {
-    env::set_var(key, value);
-    info!("Set environment variable '{}' to '{}'", key, value);
+use crate::config::EnvironmentConfig;
+
+/// The separator used when appending or prepending to a variable
+const LIST_SEPARATOR: char = ':';
+
+/// The environment variables of a session that is about to be started
+///
+/// This is built up explicitly for every session, so that nothing of the environment of Lemurs
+/// itself or of previous sessions leaks into a new session.
+#[derive(Debug, Clone, Default)]
+pub struct SessionEnvironment(BTreeMap<String, String>);
+
+impl SessionEnvironment {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set a variable and announce it in the logs
+    pub fn set(&mut self, key: &str, value: &str) {
+        self.0.insert(key.to_string(), value.to_string());
+        info!("Set environment variable '{}' to '{}'", key, value);
+    }
+
+    /// Remove a variable and announce it in the logs
+    pub fn remove(&mut self, key: &str) {
+        self.0.remove(key);
+        info!("Unset environment variable '{}'", key);
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.0.iter()
+    }
 }
 
-fn get_dotfiles_dir(homedir: &str) -> String {
-    format!("{homedir}/.files")
+/// The values that can be used within the templates of the `[environment]` configuration
+pub struct TemplateVariables<'a> {
+    pub username: &'a str,
+    pub homedir: &'a str,
+    pub shell: &'a str,
+    pub uid: u32,
+    pub gid: u32,
+    pub tty: u8,
 }
 
-/// Set all the environment variables
-pub fn init_environment(username: &str, homedir: &str, _shell: &str) {
-    env_set_and_announce("HOME", homedir);
-    let pwd = homedir;
-    if env::set_current_dir(pwd).is_ok() {
-        info!("Successfully changed working directory to {}!", pwd);
-    } else {
-        error!("Failed to change the working directory to {}", pwd);
+impl<'a> TemplateVariables<'a> {
+    fn get(&self, name: &str) -> Option<String> {
+        Some(match name {
+            "USER" => self.username.to_string(),
+            "HOME" => self.homedir.to_string(),
+            "SHELL" => self.shell.to_string(),
+            "UID" => self.uid.to_string(),
+            "GID" => self.gid.to_string(),
+            "TTY" => self.tty.to_string(),
+            _ => return None,
+        })
     }
 
-    env_set_and_announce("USER", username);
-    env_set_and_announce("LOGNAME", username);
-    env_set_and_announce("PATH", &format!("{homedir}/bin:{homedir}/bin/firefox:{homedir}/.cargo/bin:/home/linuxbrew/.linuxbrew/bin:/home/linuxbrew/.linuxbrew/sbin:/usr/local/sbin:/usr/local/bin:/usr/bin"));
-    env_set_and_announce("EDITOR", "hx");
-    env_set_and_announce("SHELL", &format!("{homedir}/.cargo/bin/nu"));
+    /// Replace all the `${NAME}` occurrences in a template with their values
+    ///
+    /// Unknown names are left as they are.
+    pub fn expand(&self, template: &str) -> String {
+        let mut expanded = String::with_capacity(template.len());
+        let mut rest = template;
+
+        while let Some(start) = rest.find("${") {
+            let Some(length) = rest[start..].find('}') else {
+                break;
+            };
+
+            expanded.push_str(&rest[..start]);
 
-    let dotfiles_dir = get_dotfiles_dir(homedir);
-    env_set_and_announce("DOTFILES_DIR", dotfiles_dir.as_str());
-    let user_bin = format!("{}/bin", homedir);
-    env_set_and_announce("USER_BIN", &user_bin);
+            let name = &rest[start + 2..start + length];
+            match self.get(name) {
+                Some(value) => expanded.push_str(&value),
+                None => {
+                    warn!("Unknown template variable '{}' in environment", name);
+                    expanded.push_str(&rest[start..=start + length]);
+                }
+            }
 
-    env_set_and_announce("PIPENV_VENV_IN_PROJECT", "1");
-    env_set_and_announce("PYTHONBREAKPOINT", "ipdb.set_trace");
-    env_set_and_announce("STARSHIP_CONFIG", &format!("{dotfiles_dir}/starship.toml"));
+            rest = &rest[start + length + 1..];
+        }
 
-    // env::set_var("MAIL", "..."); TODO: Add
+        expanded.push_str(rest);
+        expanded
+    }
+}
+
+/// Set all the environment variables
+pub fn init_environment(env: &mut SessionEnvironment, username: &str, homedir: &str, shell: &str) {
+    env.set("HOME", homedir);
+    env.set("PWD", homedir);
+    env.set("USER", username);
+    env.set("LOGNAME", username);
+    env.set("SHELL", shell);
+}
+
+/// Apply the `[environment]` section of the configuration
+///
+/// The variables are first set, then prepended to, then appended to and finally unset.
+pub fn apply_environment_config(
+    env: &mut SessionEnvironment,
+    config: &EnvironmentConfig,
+    variables: &TemplateVariables,
+) {
+    for (key, value) in &config.set {
+        env.set(key, &variables.expand(value));
+    }
+
+    for (key, value) in &config.prepend {
+        let value = variables.expand(value);
+        let value = match env.get(key) {
+            Some(current) if !current.is_empty() => format!("{value}{LIST_SEPARATOR}{current}"),
+            _ => value,
+        };
+        env.set(key, &value);
+    }
+
+    for (key, value) in &config.append {
+        let value = variables.expand(value);
+        let value = match env.get(key) {
+            Some(current) if !current.is_empty() => format!("{current}{LIST_SEPARATOR}{value}"),
+            _ => value,
+        };
+        env.set(key, &value);
+    }
+
+    for key in &config.unset {
+        env.remove(key);
+    }
 }
 
 // NOTE: This uid: u32 might be better set to libc::uid_t
 /// Set the XDG environment variables
-pub fn set_xdg_env(uid: u32, homedir: &str, tty: u8) {
-    // This is according to https://wiki.archlinux.org/title/XDG_Base_Directory
-    let dotfiles_dir = get_dotfiles_dir(homedir);
-    let xdg_config_dir = format!("{}/.config", dotfiles_dir);
-    env_set_and_announce("XDG_CONFIG_DIR", &xdg_config_dir);
-    env_set_and_announce("XDG_CONFIG_HOME", &xdg_config_dir);
-    env_set_and_announce("XDG_CACHE_HOME", &format!("{}/.cache", homedir));
-    env_set_and_announce("XDG_DATA_HOME", &format!("{}/.local/share", homedir));
-    env_set_and_announce("XDG_STATE_HOME", &format!("{}/.local/state", homedir));
-    env_set_and_announce(
-        "XDG_DATA_DIRS",
-        "/home/linuxbrew/.linuxbrew/share:/usr/local/share:/usr/share",
-    );
-    env_set_and_announce("XDG_CONFIG_DIRS", "/etc/xdg");
-
-    env_set_and_announce("ZELLIJ_CONFIG_DIR", &format!("{xdg_config_dir}/zellij"));
-    env_set_and_announce("INPUTRC", &format!("{xdg_config_dir}/readline/inputrc"));
-    env_set_and_announce("XDG_RUNTIME_DIR", &format!("/run/user/{}", uid));
-    env_set_and_announce("XDG_SESSION_DIR", "user");
-    env_set_and_announce("XDG_SESSION_ID", "1");
-    env_set_and_announce("XDG_SEAT", "seat0");
-    env_set_and_announce("XDG_VTNR", &tty.to_string());
+///
+/// These are set before the PAM environment is imported, so modules such as `pam_systemd` can
+/// override them.
+pub fn set_xdg_env(env: &mut SessionEnvironment, uid: u32, tty: u8, session_type: &str) {
+    env.set("XDG_RUNTIME_DIR", &format!("/run/user/{}", uid));
+    env.set("XDG_SESSION_TYPE", session_type);
+    env.set("XDG_SESSION_CLASS", "user");
+    env.set("XDG_SEAT", "seat0");
+    env.set("XDG_VTNR", &tty.to_string());
+}
+
+/// Import the environment variables that were set by the PAM modules
+pub fn import_pam_env(env: &mut SessionEnvironment, pam_environment: &[(String, String)]) {
+    for (key, value) in pam_environment {
+        env.set(key, value);
+    }
+}
+
+/// Set the environment variables describing the desktop that is started
+pub fn set_desktop_env(env: &mut SessionEnvironment, desktop_names: &str) {
+    env.set("XDG_CURRENT_DESKTOP", desktop_names);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables() -> TemplateVariables<'static> {
+        TemplateVariables {
+            username: "lemur",
+            homedir: "/home/lemur",
+            shell: "/bin/bash",
+            uid: 1000,
+            gid: 100,
+            tty: 2,
+        }
+    }
+
+    #[test]
+    fn template_expansion() {
+        let variables = variables();
+
+        assert_eq!(variables.expand(""), "");
+        assert_eq!(variables.expand("/usr/bin"), "/usr/bin");
+        assert_eq!(
+            variables.expand("${HOME}/.local/bin"),
+            "/home/lemur/.local/bin"
+        );
+        assert_eq!(variables.expand("/run/user/${UID}"), "/run/user/1000");
+        assert_eq!(
+            variables.expand("${USER}:${GID}@tty${TTY} ${SHELL}"),
+            "lemur:100@tty2 /bin/bash"
+        );
+        assert_eq!(variables.expand("${UNKNOWN}/x"), "${UNKNOWN}/x");
+        assert_eq!(variables.expand("a${HOME"), "a${HOME");
+    }
+
+    #[test]
+    fn environment_config() {
+        let table = |items: &[(&str, &str)]| {
+            items
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+
+        let config = EnvironmentConfig {
+            set: table(&[("PATH", "/usr/bin"), ("EDITOR", "vi"), ("A", "${HOME}")]),
+            prepend: table(&[("PATH", "${HOME}/bin"), ("B", "b")]),
+            append: table(&[("PATH", "/opt/bin"), ("A", "a")]),
+            unset: vec!["EDITOR".to_string(), "SHELL".to_string()],
+        };
+
+        let mut env = SessionEnvironment::new();
+        env.set("SHELL", "/bin/sh");
+        apply_environment_config(&mut env, &config, &variables());
+
+        assert_eq!(env.get("PATH"), Some("/home/lemur/bin:/usr/bin:/opt/bin"));
+        assert_eq!(env.get("A"), Some("/home/lemur:a"));
+        assert_eq!(env.get("B"), Some("b"));
+        assert_eq!(env.get("EDITOR"), None);
+        assert_eq!(env.get("SHELL"), None);
+    }
 }
@@ -1,5 +1,6 @@
 use log::{error, info, warn};
 use std::fs;
+use std::path::Path;
 
 use users::get_user_groups;
 
@@ -9,23 +10,36 @@ use std::process::{Command, Stdio};
 use crate::auth::utmpx::add_utmpx_entry;
 use crate::auth::AuthUserInfo;
 use crate::config::Config;
-use env_variables::{init_environment, set_xdg_env};
+use env_variables::{
+    apply_environment_config, import_pam_env, init_environment, set_desktop_env, set_xdg_env,
+    SessionEnvironment, TemplateVariables,
+};
 
 use nix::unistd::{Gid, Uid};
 
+mod desktop;
 mod env_variables;
 mod x;
 
-const INITRCS_FOLDER_PATH: &str = "/etc/lemurs/wms";
-const WAYLAND_FOLDER_PATH: &str = "/etc/lemurs/wayland";
-
 #[derive(Clone)]
 pub enum PostLoginEnvironment {
-    X { xinitrc_path: String },
-    Wayland { script_path: String },
+    X {
+        /// Path to an xinitrc script or the `Exec` command of a session file
+        xinitrc_path: String,
+        /// The value for `XDG_CURRENT_DESKTOP` taken from a session file
+        desktop_names: Option<String>,
+    },
+    Wayland {
+        /// Path to a compositor script or the `Exec` command of a session file
+        script_path: String,
+        /// The value for `XDG_CURRENT_DESKTOP` taken from a session file
+        desktop_names: Option<String>,
+    },
     Shell,
 }
 
+// NOTE: The inner errors are currently only logged at the place where they occur
+#[allow(dead_code)]
 pub enum EnvironmentStartError {
     WaylandStartError,
     XSetupError(x::XSetupError),
@@ -34,25 +48,75 @@ pub enum EnvironmentStartError {
 }
 
 impl PostLoginEnvironment {
+    /// The type of session as used by `XDG_SESSION_TYPE`
+    pub fn session_type(&self) -> &'static str {
+        match self {
+            PostLoginEnvironment::X { .. } => "x11",
+            PostLoginEnvironment::Wayland { .. } => "wayland",
+            PostLoginEnvironment::Shell => "tty",
+        }
+    }
+
     pub fn start<'a>(
         &self,
         config: &Config,
         user_info: &AuthUserInfo<'a>,
     ) -> Result<(), EnvironmentStartError> {
-        init_environment(&user_info.name, &user_info.dir, &user_info.shell);
+        // NOTE: The environment of Lemurs itself is never passed on to the session. Every session
+        // starts from an empty environment.
+        let mut env = SessionEnvironment::new();
+
+        init_environment(&mut env, &user_info.name, &user_info.dir, &user_info.shell);
         info!("Set environment variables.");
 
-        set_xdg_env(user_info.uid, &user_info.dir, config.tty);
+        set_xdg_env(&mut env, user_info.uid, config.tty, self.session_type());
         info!("Set XDG environment variables");
 
+        import_pam_env(&mut env, &user_info.environment);
+        info!("Imported PAM environment variables");
+
+        apply_environment_config(
+            &mut env,
+            &config.environment,
+            &TemplateVariables {
+                username: &user_info.name,
+                homedir: &user_info.dir,
+                shell: &user_info.shell,
+                uid: user_info.uid,
+                gid: user_info.gid,
+                tty: config.tty,
+            },
+        );
+        info!("Applied configured environment variables");
+
+        if let PostLoginEnvironment::X {
+            desktop_names: Some(desktop_names),
+            ..
+        }
+        | PostLoginEnvironment::Wayland {
+            desktop_names: Some(desktop_names),
+            ..
+        } = self
+        {
+            set_desktop_env(&mut env, desktop_names);
+        }
+
         match self {
-            PostLoginEnvironment::X { xinitrc_path } => {
-                x::setup_x(user_info).map_err(EnvironmentStartError::XSetupError)?;
-                let mut gui_environment = x::start_env(user_info, xinitrc_path)
+            PostLoginEnvironment::X { xinitrc_path, .. } => {
+                x::setup_x(user_info, &mut env).map_err(EnvironmentStartError::XSetupError)?;
+                let mut gui_environment = x::start_env(user_info, &env, xinitrc_path)
                     .map_err(EnvironmentStartError::XStartEnvError)?;
 
                 let pid = gui_environment.id();
-                let session = add_utmpx_entry(&user_info.name, config.tty, pid);
+                let session = add_utmpx_entry(
+                    &user_info.name,
+                    config.tty,
+                    pid,
+                    env.get("DISPLAY"),
+                    &config.login_records,
+                )
+                .inspect_err(|err| warn!("Failed to add UTMPX record. Reason: {}", err))
+                .ok();
 
                 gui_environment.wait().map_err(|err| {
                     warn!("Failed waiting for GUI Environment. Reason: {}", err);
@@ -61,7 +125,7 @@ impl PostLoginEnvironment {
 
                 drop(session);
             }
-            PostLoginEnvironment::Wayland { script_path } => {
+            PostLoginEnvironment::Wayland { script_path, .. } => {
                 let uid = user_info.uid;
                 let gid = user_info.gid;
                 let groups: Vec<Gid> = get_user_groups(&user_info.name, gid)
@@ -83,6 +147,9 @@ impl PostLoginEnvironment {
                 }
                 .arg("-c")
                 .arg(script_path)
+                .env_clear()
+                .envs(env.iter())
+                .current_dir(&user_info.dir)
                 .stdout(Stdio::null()) // TODO: Maybe this should be logged or something?
                 .spawn() else {
                     error!("Failed to start Wayland Compositor");
@@ -92,7 +159,15 @@ impl PostLoginEnvironment {
                 info!("Entered Wayland compositor");
                 let pid = child.id();
 
-                let session = add_utmpx_entry(&user_info.name, config.tty, pid);
+                let session = add_utmpx_entry(
+                    &user_info.name,
+                    config.tty,
+                    pid,
+                    None,
+                    &config.login_records,
+                )
+                .inspect_err(|err| warn!("Failed to add UTMPX record. Reason: {}", err))
+                .ok();
 
                 let Ok(output) = child.wait_with_output() else {
                     error!("Failed to wait on TTY shell, Reason. Returning to Lemurs...");
@@ -136,20 +211,29 @@ impl PostLoginEnvironment {
                             .map_err(|err| err.into())
                     })
                 }
+                .env_clear()
+                .envs(env.iter())
+                .current_dir(&user_info.dir)
                 .stdout(Stdio::inherit())
                 .stderr(Stdio::inherit())
                 .stdin(Stdio::inherit())
                 .spawn() else {
-                    error!(
-                        "Failed to start TTY shell. Returning to Lemurs...",
-                    );
+                    error!("Failed to start TTY shell. Returning to Lemurs...");
                     return Ok(());
                 };
 
                 info!("Entered TTY");
                 let pid = child.id();
 
-                let session = add_utmpx_entry(&user_info.name, config.tty, pid);
+                let session = add_utmpx_entry(
+                    &user_info.name,
+                    config.tty,
+                    pid,
+                    None,
+                    &config.login_records,
+                )
+                .inspect_err(|err| warn!("Failed to add UTMPX record. Reason: {}", err))
+                .ok();
 
                 let Ok(output) = child.wait_with_output() else {
                     error!("Failed to wait on TTY shell, Reason. Returning to Lemurs...");
@@ -178,56 +262,62 @@ impl PostLoginEnvironment {
     }
 }
 
-pub fn get_envs(with_tty_shell: bool) -> Vec<(String, PostLoginEnvironment)> {
+pub fn get_envs(config: &Config) -> Vec<(String, PostLoginEnvironment)> {
     // NOTE: Maybe we can do something smart with `with_capacity` here.
     let mut envs = Vec::new();
 
-    match fs::read_dir(INITRCS_FOLDER_PATH) {
-        Ok(paths) => {
-            for path in paths {
-                if let Ok(path) = path {
-                    let file_name = path.file_name().into_string();
+    for (file_name, script_path) in get_scripts(&config.x11.scripts_path, "X") {
+        envs.push((
+            file_name,
+            PostLoginEnvironment::X {
+                xinitrc_path: script_path,
+                desktop_names: None,
+            },
+        ));
+    }
 
-                    if let Ok(file_name) = file_name {
-                        if let Ok(metadata) = path.metadata() {
-                            if std::os::unix::fs::MetadataExt::mode(&metadata) & 0o111 == 0 {
-                                warn!(
-                            "'{}' is not executable and therefore not added as an environment",
-                            file_name
-                        );
+    for session in desktop::get_desktop_sessions(Path::new(&config.x11.xsessions_path)) {
+        envs.push((
+            session.name,
+            PostLoginEnvironment::X {
+                xinitrc_path: session.exec,
+                desktop_names: session.desktop_names,
+            },
+        ));
+    }
 
-                                continue;
-                            }
-                        }
+    for (file_name, script_path) in get_scripts(&config.wayland.scripts_path, "wayland") {
+        envs.push((
+            file_name,
+            PostLoginEnvironment::Wayland {
+                script_path,
+                desktop_names: None,
+            },
+        ));
+    }
 
-                        envs.push((
-                            file_name,
-                            PostLoginEnvironment::X {
-                                xinitrc_path: match path.path().to_str() {
-                                    Some(p) => p.to_string(),
-                                    None => {
-                                        warn!(
-                                    "Skipped item because it was impossible to convert to string"
-                                );
-                                        continue;
-                                    }
-                                },
-                            },
-                        ));
-                    } else {
-                        warn!("Unable to convert OSString to String");
-                    }
-                } else {
-                    warn!("Ignored errorinous path: '{}'", path.unwrap_err());
-                }
-            }
-        }
-        Err(_) => {
-            warn!("Failed to read from the X folder '{}'", INITRCS_FOLDER_PATH);
-        }
+    for session in desktop::get_desktop_sessions(Path::new(&config.wayland.wayland_sessions_path)) {
+        envs.push((
+            session.name,
+            PostLoginEnvironment::Wayland {
+                script_path: session.exec,
+                desktop_names: session.desktop_names,
+            },
+        ));
+    }
+
+    if envs.is_empty() || config.environment_switcher.include_tty_shell {
+        envs.push(("TTYSHELL".to_string(), PostLoginEnvironment::Shell));
     }
 
-    match fs::read_dir(WAYLAND_FOLDER_PATH) {
+    envs
+}
+
+/// Get the file names and paths of all the executable scripts within a folder
+fn get_scripts(folder_path: &str, kind: &str) -> Vec<(String, String)> {
+    let mut scripts = Vec::new();
+
+    match fs::read_dir(folder_path) {
         Ok(paths) => {
             for path in paths {
                 if let Ok(path) = path {
@@ -245,20 +335,14 @@ pub fn get_envs(with_tty_shell: bool) -> Vec<(String, PostLoginEnvironment)> {
                             }
                         }
 
-                        envs.push((
-                            file_name,
-                            PostLoginEnvironment::Wayland {
-                                script_path: match path.path().to_str() {
-                                    Some(p) => p.to_string(),
-                                    None => {
-                                        warn!(
+                        match path.path().to_str() {
+                            Some(p) => scripts.push((file_name, p.to_string())),
+                            None => {
+                                warn!(
                                     "Skipped item because it was impossible to convert to string"
                                 );
-                                        continue;
-                                    }
-                                },
-                            },
-                        ));
+                            }
+                        }
                     } else {
                         warn!("Unable to convert OSString to String");
                     }
@@ -268,16 +352,9 @@ pub fn get_envs(with_tty_shell: bool) -> Vec<(String, PostLoginEnvironment)> {
             }
         }
         Err(_) => {
-            warn!(
-                "Failed to read from the wayland folder '{}'",
-                WAYLAND_FOLDER_PATH
-            );
+            warn!("Failed to read from the {} folder '{}'", kind, folder_path);
         }
     }
 
-    if envs.is_empty() || with_tty_shell {
-        envs.push(("TTYSHELL".to_string(), PostLoginEnvironment::Shell));
-    }
-
-    envs
+    scripts
 }
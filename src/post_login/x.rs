@@ -1,6 +1,5 @@
 use nix::unistd::{Gid, Uid};
 use rand::Rng;
-use std::env;
 use std::os::unix::process::CommandExt;
 use std::process::{Child, Command, Stdio};
 use std::{thread, time};
@@ -11,6 +10,7 @@ use std::path::PathBuf;
 use log::{error, info};
 
 use crate::auth::AuthUserInfo;
+use crate::post_login::env_variables::SessionEnvironment;
 
 const DISPLAY: &str = ":1";
 const VIRTUAL_TERMINAL: &str = "vt01";
@@ -37,20 +37,24 @@ fn mcookie() -> String {
     format!("{:032x}", cookie)
 }
 
-pub fn setup_x(user_info: &AuthUserInfo) -> Result<Child, XSetupError> {
+pub fn setup_x(
+    user_info: &AuthUserInfo,
+    env: &mut SessionEnvironment,
+) -> Result<Child, XSetupError> {
     info!("Start setup of X");
 
     // Setup xauth
-    let xauth_dir =
-        PathBuf::from(env::var("XDG_CONFIG_HOME").unwrap_or_else(|_| user_info.dir.to_string()));
+    let xauth_dir = PathBuf::from(env.get("XDG_CONFIG_HOME").unwrap_or(&user_info.dir));
     let xauth_path = xauth_dir.join(".Xauthority");
-    env::set_var("XAUTHORITY", xauth_path);
-    env::set_var("DISPLAY", DISPLAY);
+    env.set("XAUTHORITY", &xauth_path.to_string_lossy());
+    env.set("DISPLAY", DISPLAY);
 
     info!("Filling Xauthority file");
     Command::new(SYSTEM_SHELL)
         .arg("-c")
         .arg(format!("/usr/bin/xauth add {} . {}", DISPLAY, mcookie()))
+        .env_clear()
+        .envs(env.iter())
         .stdout(Stdio::null()) // TODO: Maybe this should be logged or something?
         .stderr(Stdio::null()) // TODO: Maybe this should be logged or something?
         .status()
@@ -63,6 +67,8 @@ pub fn setup_x(user_info: &AuthUserInfo) -> Result<Child, XSetupError> {
     let child = Command::new(SYSTEM_SHELL)
         .arg("-c")
         .arg(format!("/usr/bin/X {} {}", DISPLAY, VIRTUAL_TERMINAL))
+        .env_clear()
+        .envs(env.iter())
         .stdout(Stdio::null()) // TODO: Maybe this should be logged or something?
         .stderr(Stdio::null()) // TODO: Maybe this should be logged or something?
         .spawn()
@@ -88,6 +94,8 @@ pub fn setup_x(user_info: &AuthUserInfo) -> Result<Child, XSetupError> {
         match Command::new(SYSTEM_SHELL)
             .arg("-c")
             .arg("timeout 1s /usr/bin/xset q")
+            .env_clear()
+            .envs(env.iter())
             .stdout(Stdio::null()) // TODO: Maybe this should be logged or something?
             .stderr(Stdio::null()) // TODO: Maybe this should be logged or something?
             .status()
@@ -110,7 +118,11 @@ pub fn setup_x(user_info: &AuthUserInfo) -> Result<Child, XSetupError> {
     Ok(child)
 }
 
-pub fn start_env(user_info: &AuthUserInfo, script_path: &str) -> Result<Child, XStartEnvError> {
+pub fn start_env(
+    user_info: &AuthUserInfo,
+    env: &SessionEnvironment,
+    script_path: &str,
+) -> Result<Child, XStartEnvError> {
     let uid = user_info.uid;
     let gid = user_info.gid;
     let groups: Vec<Gid> = get_user_groups(&user_info.name, gid)
@@ -124,6 +136,9 @@ pub fn start_env(user_info: &AuthUserInfo, script_path: &str) -> Result<Child, X
     let cmd = cmd
         .arg("-c")
         .arg(format!("{} {}", "/etc/lemurs/xsetup.sh", script_path))
+        .env_clear()
+        .envs(env.iter())
+        .current_dir(&user_info.dir)
         .stdout(Stdio::null()) // TODO: Maybe this should be logged or something?
         .stderr(Stdio::null()); // TODO: Maybe this should be logged or something?
     let cmd = unsafe {
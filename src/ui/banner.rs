@@ -0,0 +1,179 @@
+use std::fs::read_to_string;
+
+use log::warn;
+use nix::sys::utsname::uname;
+use tui::backend::Backend;
+use tui::layout::Rect;
+use tui::style::Style;
+use tui::text::Text;
+use tui::widgets::Paragraph;
+use tui::Frame;
+use unicode_width::UnicodeWidthStr;
+
+use crate::config::{get_color, get_modifiers, BannerConfig, HorizontalAlignment};
+
+/// The values that are filled in for the `/etc/issue` escapes
+struct SystemInfo {
+    sysname: String,
+    nodename: String,
+    release: String,
+    machine: String,
+    tty: u8,
+    date: String,
+    time: String,
+}
+
+impl SystemInfo {
+    fn current(tty: u8) -> Self {
+        let uts = uname();
+        let now = chrono::Local::now();
+
+        Self {
+            sysname: uts.sysname().to_string(),
+            nodename: uts.nodename().to_string(),
+            release: uts.release().to_string(),
+            machine: uts.machine().to_string(),
+            tty,
+            date: now.format("%a %b %e %Y").to_string(),
+            time: now.format("%H:%M:%S").to_string(),
+        }
+    }
+}
+
+/// Fill in the escapes of `/etc/issue` that agetty knows. Other escapes are left as they are.
+fn expand_escapes(text: &str, info: &SystemInfo) -> String {
+    let mut expanded = String::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            expanded.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('s') => expanded.push_str(&info.sysname),
+            Some('n') => expanded.push_str(&info.nodename),
+            Some('r') => expanded.push_str(&info.release),
+            Some('m') => expanded.push_str(&info.machine),
+            Some('l') => expanded.push_str(&format!("tty{}", info.tty)),
+            Some('d') => expanded.push_str(&info.date),
+            Some('t') => expanded.push_str(&info.time),
+            Some('\\') => expanded.push('\\'),
+            Some(other) => {
+                expanded.push('\\');
+                expanded.push(other);
+            }
+            None => expanded.push('\\'),
+        }
+    }
+
+    expanded
+}
+
+/// A header with ASCII art or text above the login form
+#[derive(Clone)]
+pub struct BannerWidget {
+    config: BannerConfig,
+    /// The text before the escapes are filled in
+    template: String,
+    tty: u8,
+}
+
+impl BannerWidget {
+    pub fn new(config: BannerConfig, tty: u8) -> Self {
+        let template = if !config.show {
+            String::new()
+        } else if config.file.is_empty() {
+            config.text.clone()
+        } else {
+            read_to_string(&config.file).unwrap_or_else(|err| {
+                warn!(
+                    "Failed to read the banner file '{}'. Reason: {}",
+                    config.file, err
+                );
+                config.text.clone()
+            })
+        };
+
+        // Trailing empty lines (e.g. at the end of `/etc/issue`) would only take up space
+        let template = template.trim_end().to_string();
+
+        Self {
+            config,
+            template,
+            tty,
+        }
+    }
+
+    /// The number of lines the banner takes up
+    pub fn height(&self) -> u16 {
+        self.template.lines().count().try_into().unwrap_or(u16::MAX)
+    }
+
+    fn style(&self) -> Style {
+        let mut style = Style::default().fg(get_color(&self.config.text_color));
+
+        for modifier in get_modifiers(&self.config.text_modifiers) {
+            style = style.add_modifier(modifier);
+        }
+
+        style
+    }
+
+    pub fn render(&self, frame: &mut Frame<impl Backend>, area: Rect) {
+        if self.template.is_empty() || area.area() == 0 {
+            return;
+        }
+
+        let text = expand_escapes(&self.template, &SystemInfo::current(self.tty));
+
+        // The lines are aligned as one block, so that ASCII art stays intact. Lines that are too
+        // long are cut off at the edge of the area.
+        let width = text
+            .lines()
+            .map(UnicodeWidthStr::width)
+            .max()
+            .unwrap_or_default()
+            .try_into()
+            .unwrap_or(u16::MAX)
+            .min(area.width);
+        let free_width = area.width - width;
+        let x = area.x
+            + match self.config.alignment {
+                HorizontalAlignment::Left => 0,
+                HorizontalAlignment::Center => free_width / 2,
+                HorizontalAlignment::Right => free_width,
+            };
+
+        let widget = Paragraph::new(Text::raw(text)).style(self.style());
+        frame.render_widget(widget, Rect::new(x, area.y, width, area.height));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn issue_escapes() {
+        let info = SystemInfo {
+            sysname: String::from("Linux"),
+            nodename: String::from("lemur-01"),
+            release: String::from("6.1.0"),
+            machine: String::from("x86_64"),
+            tty: 2,
+            date: String::from("Sat Oct 17 2026"),
+            time: String::from("12:00:00"),
+        };
+
+        assert_eq!(
+            expand_escapes("\\s \\r (\\m) on \\n at \\l\n\\d \\t", &info),
+            "Linux 6.1.0 (x86_64) on lemur-01 at tty2\nSat Oct 17 2026 12:00:00"
+        );
+        assert_eq!(
+            expand_escapes("\\\\n \\e{red} \\", &info),
+            "\\n \\e{red} \\"
+        );
+    }
+}
@@ -1,47 +1,303 @@
-use tui::{
-    backend::Backend,
-    layout::{Constraint, Direction, Layout, Rect},
-    Frame,
+use tui::{backend::Backend, layout::Rect, Frame};
+
+use crate::config::{
+    FormWidget, HorizontalAlignment, LayoutConfig, StatusBarPosition, VerticalAlignment,
 };
-use Constraint::{Length, Min};
+
+/// The lines between the power menu and the top of the form
+const POWER_MENU_GAP: u16 = 3;
+
+const DEFAULT_ORDER: [FormWidget; 4] = [
+    FormWidget::Switcher,
+    FormWidget::Username,
+    FormWidget::Password,
+    FormWidget::StatusMessage,
+];
 
 pub struct Chunks {
     pub power_menu: Rect,
+    pub status_bar: Rect,
+    pub banner: Rect,
     pub switcher: Rect,
     pub username_field: Rect,
     pub password_field: Rect,
     pub status_message: Rect,
+    /// The area of the border around the form, if it is shown
+    pub border: Option<Rect>,
+}
+
+fn widget_height(widget: FormWidget) -> u16 {
+    match widget {
+        FormWidget::Switcher | FormWidget::StatusMessage => 1,
+        FormWidget::Username | FormWidget::Password => 3,
+    }
 }
 
 impl Chunks {
-    pub fn new<B: Backend>(frame: &mut Frame<B>) -> Self {
-        let constraints = [
-            Length(1),
-            Length(1),
-            Length(2),
-            Length(1),
-            Length(2),
-            Length(3),
-            Length(2),
-            Length(3),
-            Length(2),
-            Length(1),
-            Min(0),
-        ];
-
-        let chunks = Layout::default()
-            .direction(Direction::Vertical)
-            .horizontal_margin(2)
-            .vertical_margin(1)
-            .constraints(constraints.as_ref())
-            .split(frame.size());
-
-        Self {
-            power_menu: chunks[0],
-            switcher: chunks[3],
-            username_field: chunks[5],
-            password_field: chunks[7],
-            status_message: chunks[9],
+    pub fn new<B: Backend>(
+        frame: &mut Frame<B>,
+        config: &LayoutConfig,
+        banner_height: u16,
+        status_bar: Option<StatusBarPosition>,
+    ) -> Self {
+        Self::compute(frame.size(), config, banner_height, status_bar)
+    }
+
+    fn compute(
+        area: Rect,
+        config: &LayoutConfig,
+        banner_height: u16,
+        status_bar: Option<StatusBarPosition>,
+    ) -> Self {
+        // The status bar takes the line at the edge of the screen, outside of the vertical margin
+        let status_bar_height = u16::from(status_bar.is_some()).min(area.height);
+        let status_bar_y = match status_bar {
+            Some(StatusBarPosition::Bottom) => area.bottom() - status_bar_height,
+            _ => area.y,
+        };
+        let status_bar = Rect::new(
+            area.x + config.horizontal_margin.min(area.width / 2),
+            status_bar_y,
+            area.width
+                .saturating_sub(config.horizontal_margin.saturating_mul(2)),
+            status_bar_height,
+        );
+        let area = Rect::new(
+            area.x,
+            if status_bar_y == area.y {
+                area.y + status_bar_height
+            } else {
+                area.y
+            },
+            area.width,
+            area.height - status_bar_height,
+        );
+
+        let area = Rect::new(
+            area.x + config.horizontal_margin.min(area.width / 2),
+            area.y + config.vertical_margin.min(area.height / 2),
+            area.width
+                .saturating_sub(config.horizontal_margin.saturating_mul(2)),
+            area.height
+                .saturating_sub(config.vertical_margin.saturating_mul(2)),
+        );
+
+        let power_menu = Rect::new(area.x, area.y, area.width, area.height.min(1));
+
+        let form_top = (1 + POWER_MENU_GAP).min(area.height);
+        let form_area = Rect::new(
+            area.x,
+            area.y + form_top,
+            area.width,
+            area.height - form_top,
+        );
+
+        // Every widget is shown once, even if it is left out of the order
+        let mut order: Vec<FormWidget> = Vec::new();
+        for widget in config.order.iter().chain(DEFAULT_ORDER.iter()) {
+            if !order.contains(widget) {
+                order.push(*widget);
+            }
+        }
+
+        // The banner sits above the widgets, if there is one
+        let banner_space = match banner_height {
+            0 => 0,
+            height => height.saturating_add(config.spacing),
+        };
+
+        // The configured sizes can be anything, so they are added up without overflowing
+        let content_height = order
+            .iter()
+            .copied()
+            .map(widget_height)
+            .fold(0, u16::saturating_add)
+            .saturating_add(config.spacing.saturating_mul(order.len() as u16 - 1))
+            .saturating_add(banner_space);
+
+        // The border takes a line at the top and bottom and is padded with a space on the sides
+        let (border_width, border_height) = if config.show_border { (4, 2) } else { (0, 0) };
+
+        let width = match config.max_width {
+            0 => form_area.width,
+            max_width => max_width.saturating_add(border_width).min(form_area.width),
+        };
+        let height = content_height
+            .saturating_add(border_height)
+            .min(form_area.height);
+
+        let free_width = form_area.width - width;
+        let x = form_area.x
+            + match config.horizontal_alignment {
+                HorizontalAlignment::Left => 0,
+                HorizontalAlignment::Center => free_width / 2,
+                HorizontalAlignment::Right => free_width,
+            };
+
+        let free_height = form_area.height - height;
+        let y = form_area.y
+            + match config.vertical_alignment {
+                VerticalAlignment::Top => 0,
+                VerticalAlignment::Center => free_height / 2,
+                VerticalAlignment::Bottom => free_height,
+            };
+
+        let form = Rect::new(x, y, width, height);
+
+        let content = Rect::new(
+            form.x + (border_width / 2).min(form.width / 2),
+            form.y + (border_height / 2).min(form.height / 2),
+            form.width.saturating_sub(border_width),
+            form.height.saturating_sub(border_height),
+        );
+
+        let mut chunks = Self {
+            power_menu,
+            status_bar,
+            banner: Rect::new(
+                content.x,
+                content.y,
+                content.width,
+                banner_height.min(content.height),
+            ),
+            switcher: Rect::default(),
+            username_field: Rect::default(),
+            password_field: Rect::default(),
+            status_message: Rect::default(),
+            border: config.show_border.then_some(form),
+        };
+
+        let mut line = content.y.saturating_add(banner_space);
+        for widget in order {
+            let height = widget_height(widget);
+            // Widgets that do not fit anymore get no lines
+            let top = line.min(content.bottom());
+            let bottom = line.saturating_add(height).min(content.bottom());
+            let rect = Rect::new(content.x, top, content.width, bottom - top);
+
+            match widget {
+                FormWidget::Switcher => chunks.switcher = rect,
+                FormWidget::Username => chunks.username_field = rect,
+                FormWidget::Password => chunks.password_field = rect,
+                FormWidget::StatusMessage => chunks.status_message = rect,
+            }
+
+            line = line.saturating_add(height.saturating_add(config.spacing));
+        }
+
+        chunks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn default_layout() {
+        let config = Config::default().layout;
+        let chunks = Chunks::compute(Rect::new(0, 0, 80, 24), &config, 0, None);
+
+        assert_eq!(chunks.power_menu, Rect::new(2, 1, 76, 1));
+        assert_eq!(chunks.banner.height, 0);
+        assert_eq!(chunks.switcher, Rect::new(2, 5, 76, 1));
+        assert_eq!(chunks.username_field, Rect::new(2, 8, 76, 3));
+        assert_eq!(chunks.password_field, Rect::new(2, 13, 76, 3));
+        assert_eq!(chunks.status_message, Rect::new(2, 18, 76, 1));
+        assert_eq!(chunks.border, None);
+    }
+
+    #[test]
+    fn centered_layout() {
+        let mut config = Config::default().layout;
+        config.vertical_alignment = VerticalAlignment::Center;
+        config.horizontal_alignment = HorizontalAlignment::Center;
+        config.order = vec![FormWidget::Username, FormWidget::Password];
+        config.spacing = 1;
+        config.max_width = 30;
+        config.show_border = true;
+
+        let chunks = Chunks::compute(Rect::new(0, 0, 80, 24), &config, 0, None);
+
+        // 3 + 1 + 3 + 1 + 1 + 1 + 1 lines of content with a border around it
+        assert_eq!(chunks.border, Some(Rect::new(23, 7, 34, 13)));
+        assert_eq!(chunks.username_field, Rect::new(25, 8, 30, 3));
+        assert_eq!(chunks.password_field, Rect::new(25, 12, 30, 3));
+        assert_eq!(chunks.switcher, Rect::new(25, 16, 30, 1));
+        assert_eq!(chunks.status_message, Rect::new(25, 18, 30, 1));
+
+        // Everything stays on the screen when it is too small
+        let chunks = Chunks::compute(Rect::new(0, 0, 10, 6), &config, 0, None);
+        for rect in [
+            chunks.switcher,
+            chunks.username_field,
+            chunks.status_message,
+        ] {
+            assert!(rect.right() <= 10 && rect.bottom() <= 6);
+        }
+    }
+
+    #[test]
+    fn banner() {
+        let config = Config::default().layout;
+        let chunks = Chunks::compute(Rect::new(0, 0, 80, 40), &config, 4, None);
+
+        assert_eq!(chunks.banner, Rect::new(2, 5, 76, 4));
+        assert_eq!(chunks.switcher, Rect::new(2, 11, 76, 1));
+
+        // A banner that is too high is cut off
+        let chunks = Chunks::compute(Rect::new(0, 0, 80, 10), &config, 20, None);
+        assert_eq!(chunks.banner, Rect::new(2, 5, 76, 4));
+        assert_eq!(chunks.switcher.height, 0);
+    }
+
+    #[test]
+    fn status_bar() {
+        let config = Config::default().layout;
+
+        let chunks = Chunks::compute(
+            Rect::new(0, 0, 80, 24),
+            &config,
+            0,
+            Some(StatusBarPosition::Top),
+        );
+        assert_eq!(chunks.status_bar, Rect::new(2, 0, 76, 1));
+        assert_eq!(chunks.power_menu, Rect::new(2, 2, 76, 1));
+
+        let chunks = Chunks::compute(
+            Rect::new(0, 0, 80, 24),
+            &config,
+            0,
+            Some(StatusBarPosition::Bottom),
+        );
+        assert_eq!(chunks.status_bar, Rect::new(2, 23, 76, 1));
+        assert_eq!(chunks.power_menu, Rect::new(2, 1, 76, 1));
+        assert_eq!(chunks.status_message, Rect::new(2, 18, 76, 1));
+    }
+
+    #[test]
+    fn huge_sizes() {
+        let mut config = Config::default().layout;
+        config.horizontal_margin = u16::MAX;
+        config.vertical_margin = u16::MAX;
+        config.spacing = u16::MAX;
+        config.max_width = u16::MAX;
+        config.show_border = true;
+
+        // Nothing overflows and everything stays on the screen
+        let area = Rect::new(0, 0, 80, 24);
+        let chunks = Chunks::compute(area, &config, u16::MAX, Some(StatusBarPosition::Top));
+        for rect in [
+            chunks.power_menu,
+            chunks.status_bar,
+            chunks.banner,
+            chunks.switcher,
+            chunks.username_field,
+            chunks.password_field,
+            chunks.status_message,
+        ] {
+            assert!(rect.right() <= area.right() && rect.bottom() <= area.bottom());
         }
     }
 }
@@ -0,0 +1,82 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crossterm::event::{self, Event, KeyEvent};
+use log::error;
+
+use super::UIThreadRequest;
+
+/// How often the UI thread gets a tick to update what changes over time, such as the clock
+const TICK_RATE: Duration = Duration::from_millis(250);
+
+/// Whether the UI thread has stopped, which is noticed once a tick cannot be sent anymore
+#[derive(Clone, Default)]
+pub struct UIThreadStopped(Arc<AtomicBool>);
+
+impl UIThreadStopped {
+    fn get(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Send a tick to the UI thread every `TICK_RATE` until the UI thread has stopped
+///
+/// The ticks come from their own thread, so that they continue whilst the input thread is busy
+/// (e.g. whilst PAM is authenticating).
+pub fn spawn_ticker(ui_requests: Sender<UIThreadRequest>) -> UIThreadStopped {
+    let stopped = UIThreadStopped::default();
+
+    let ticker_stopped = stopped.clone();
+    thread::spawn(move || loop {
+        thread::sleep(TICK_RATE);
+
+        if ui_requests.send(UIThreadRequest::Tick).is_err() {
+            ticker_stopped.0.store(true, Ordering::Relaxed);
+            break;
+        }
+    });
+
+    stopped
+}
+
+/// Reads the events of the terminal and redraws the login form when the terminal is resized
+pub struct EventReader {
+    ui_requests: Sender<UIThreadRequest>,
+    ui_stopped: UIThreadStopped,
+}
+
+impl EventReader {
+    pub fn new(ui_requests: Sender<UIThreadRequest>, ui_stopped: UIThreadStopped) -> Self {
+        Self {
+            ui_requests,
+            ui_stopped,
+        }
+    }
+
+    /// Wait for the next key that is pressed. A resize of the terminal is redrawn right away.
+    ///
+    /// Returns `None` once the events cannot be read anymore or the UI thread has stopped.
+    pub fn next_key(&self) -> Option<KeyEvent> {
+        loop {
+            let event = match event::poll(TICK_RATE) {
+                Ok(false) if self.ui_stopped.get() => return None,
+                Ok(false) => continue,
+                Ok(true) => event::read(),
+                Err(err) => Err(err),
+            };
+
+            match event {
+                Ok(Event::Key(key)) => return Some(key),
+                Ok(Event::Resize(_, _)) => self.ui_requests.send(UIThreadRequest::Redraw).ok()?,
+                Ok(Event::Mouse(_)) => {}
+                Err(err) => {
+                    error!("Failed to read the terminal events. Reason: {}", err);
+                    return None;
+                }
+            }
+        }
+    }
+}
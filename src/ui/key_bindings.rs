@@ -0,0 +1,182 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use log::{error, info};
+
+use crate::config::{Config, KeyBindingsPreset, KeySpec};
+
+/// Everything that can be done with a key binding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    NextField,
+    PrevField,
+    Submit,
+    SwitcherLeft,
+    SwitcherRight,
+    ClearField,
+    SaveCache,
+    Shutdown,
+    Reboot,
+}
+
+const DEFAULT_PRESET: &[(Action, &[&str])] = &[
+    (Action::NextField, &["Enter", "Down", "Tab"]),
+    (Action::PrevField, &["Up", "Shift-Tab"]),
+    (Action::Submit, &["Enter"]),
+    (Action::SwitcherLeft, &["Left", "h"]),
+    (Action::SwitcherRight, &["Right", "l"]),
+    (Action::ClearField, &["Ctrl-u"]),
+    (Action::SaveCache, &["s"]),
+];
+
+const VI_PRESET: &[(Action, &[&str])] = &[
+    (
+        Action::NextField,
+        &["Enter", "Tab", "Down", "j", "Ctrl-j", "Ctrl-n"],
+    ),
+    (
+        Action::PrevField,
+        &["Shift-Tab", "Up", "k", "Ctrl-k", "Ctrl-p"],
+    ),
+    (Action::Submit, &["Enter"]),
+    (Action::SwitcherLeft, &["h", "Left"]),
+    (Action::SwitcherRight, &["l", "Right"]),
+    (Action::ClearField, &["Ctrl-u"]),
+    (Action::SaveCache, &["Ctrl-s"]),
+];
+
+#[derive(Debug, Clone)]
+struct KeyBinding {
+    action: Action,
+    /// The key specification as it was configured
+    spec: String,
+    key: KeySpec,
+}
+
+/// The keys that are bound to every action
+#[derive(Debug, Clone)]
+pub struct KeyBindings(Vec<KeyBinding>);
+
+impl KeyBindings {
+    /// Combine the preset with the actions that are set in the `[keybindings]` section. The power
+    /// actions default to the keys of the `[power_controls]` section.
+    pub fn new(config: &Config) -> Self {
+        let bindings = &config.keybindings;
+
+        let preset = match bindings.preset {
+            KeyBindingsPreset::Default => DEFAULT_PRESET,
+            KeyBindingsPreset::Vi => VI_PRESET,
+        };
+
+        let configured = [
+            (Action::NextField, &bindings.next_field),
+            (Action::PrevField, &bindings.prev_field),
+            (Action::Submit, &bindings.submit),
+            (Action::SwitcherLeft, &bindings.switcher_left),
+            (Action::SwitcherRight, &bindings.switcher_right),
+            (Action::ClearField, &bindings.clear_field),
+            (Action::SaveCache, &bindings.save_cache),
+            (Action::Shutdown, &bindings.shutdown),
+            (Action::Reboot, &bindings.reboot),
+        ];
+
+        let mut key_bindings = Vec::new();
+        for (action, specs) in configured {
+            let specs: Vec<&str> = match specs {
+                Some(specs) => {
+                    info!("Using the configured keys for {:?}", action);
+                    specs.iter().map(String::as_str).collect()
+                }
+                None => match action {
+                    Action::Shutdown => vec![&config.power_controls.shutdown_key[..]],
+                    Action::Reboot => vec![&config.power_controls.reboot_key[..]],
+                    _ => preset
+                        .iter()
+                        .filter(|(preset_action, _)| *preset_action == action)
+                        .flat_map(|(_, specs)| specs.iter().copied())
+                        .collect(),
+                },
+            };
+
+            for spec in specs {
+                match KeySpec::parse(spec) {
+                    Some(key) => key_bindings.push(KeyBinding {
+                        action,
+                        spec: spec.to_string(),
+                        key,
+                    }),
+                    None => error!("Did not recognize the key '{}' for {:?}", spec, action),
+                }
+            }
+        }
+
+        Self(key_bindings)
+    }
+
+    /// Whether a key event is bound to an action
+    ///
+    /// Whilst typing in a text field, keys that type a character are passed to the field instead.
+    pub fn is(&self, action: Action, key: &KeyEvent, in_text_field: bool) -> bool {
+        let types_character = matches!(key.code, KeyCode::Char(_))
+            && (key.modifiers - KeyModifiers::SHIFT).is_empty();
+
+        if in_text_field && types_character {
+            return false;
+        }
+
+        self.0
+            .iter()
+            .any(|binding| binding.action == action && binding.key.matches(key))
+    }
+
+    /// The first key that is bound to an action as it was configured
+    pub fn hint(&self, action: Action) -> &str {
+        self.0
+            .iter()
+            .find(|binding| binding.action == action)
+            .map_or("", |binding| &binding.spec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent { code, modifiers }
+    }
+
+    #[test]
+    fn presets_and_overrides() {
+        let mut config = Config::default();
+        let bindings = KeyBindings::new(&config);
+
+        let j = key(KeyCode::Char('j'), KeyModifiers::NONE);
+        let s = key(KeyCode::Char('s'), KeyModifiers::NONE);
+        let f1 = key(KeyCode::F(1), KeyModifiers::NONE);
+
+        assert!(bindings.is(
+            Action::NextField,
+            &key(KeyCode::Tab, KeyModifiers::NONE),
+            true
+        ));
+        assert!(bindings.is(Action::SaveCache, &s, false));
+        assert!(!bindings.is(Action::SaveCache, &s, true));
+        assert!(!bindings.is(Action::NextField, &j, false));
+        assert!(bindings.is(Action::Shutdown, &f1, false));
+        assert_eq!(bindings.hint(Action::Shutdown), "F1");
+
+        config.keybindings.preset = KeyBindingsPreset::Vi;
+        config.keybindings.shutdown = Some(vec![String::from("Ctrl-q"), String::from("???")]);
+        let bindings = KeyBindings::new(&config);
+
+        assert!(bindings.is(Action::NextField, &j, false));
+        assert!(!bindings.is(Action::NextField, &j, true));
+        assert!(!bindings.is(Action::SaveCache, &s, false));
+        assert!(!bindings.is(Action::Shutdown, &f1, false));
+        assert!(bindings.is(
+            Action::Shutdown,
+            &key(KeyCode::Char('q'), KeyModifiers::CONTROL),
+            true
+        ));
+        assert_eq!(bindings.hint(Action::Shutdown), "Ctrl-q");
+    }
+}
@@ -1,37 +1,66 @@
 use log::{error, info, warn};
 
 use std::io;
-use std::sync::mpsc::channel;
+use std::sync::mpsc::{channel, Sender};
 use std::sync::{Arc, Mutex, MutexGuard};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::auth::{AuthUserInfo, AuthenticationError};
-use crate::config::{Config, FocusBehaviour};
+use crate::auth::throttle::{self, FailedLogins, LoginBlock};
+use crate::auth::utmpx::{add_btmp_entry, update_lastlog};
+use crate::auth::{AuthUserInfo, AuthenticationError, Conversation};
+use crate::config::{
+    get_color, Config, FocusBehaviour, InputFieldStyle, LayoutConfig, StatusBarPosition,
+};
 use crate::info_caching::{get_cached_information, set_cache};
 use crate::post_login::{EnvironmentStartError, PostLoginEnvironment};
 use status_message::StatusMessage;
 
 use crossterm::cursor::MoveTo;
-use crossterm::event::{self, Event, KeyCode, KeyModifiers};
+use crossterm::event::{KeyCode, KeyEvent};
 use crossterm::execute;
 use crossterm::terminal::{
     disable_raw_mode, enable_raw_mode, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen,
 };
 use tui::backend::CrosstermBackend;
+use tui::style::Style;
+use tui::text::Span;
+use tui::widgets::{Block, Borders};
 use tui::{backend::Backend, Frame, Terminal};
 
+mod banner;
 mod chunks;
+mod events;
 mod input_field;
+mod key_bindings;
 mod power_menu;
+mod status_bar;
 mod status_message;
 mod switcher;
 
+use banner::BannerWidget;
 use chunks::Chunks;
+use events::{spawn_ticker, EventReader};
 use input_field::{InputFieldDisplayType, InputFieldWidget};
+use key_bindings::{Action, KeyBindings};
 use power_menu::PowerMenuWidget;
+use status_bar::StatusBarWidget;
 use status_message::{ErrorStatusMessage, InfoStatusMessage};
 use switcher::{SwitcherItem, SwitcherWidget};
 
+/// Lock a mutex that is shared between the threads of the login form
+///
+/// The mutex is only poisoned when another thread panicked whilst holding it, after which the
+/// login form cannot be used anymore.
+fn lock_or_exit<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    match mutex.lock() {
+        Ok(guard) => guard,
+        Err(err) => {
+            error!("Lock failed. Reason: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
 #[derive(Clone)]
 struct LoginFormInputMode(Arc<Mutex<InputMode>>);
 
@@ -40,16 +69,9 @@ impl LoginFormInputMode {
         Self(Arc::new(Mutex::new(mode)))
     }
 
-    fn get_guard(&self) -> MutexGuard<InputMode> {
+    fn get_guard(&self) -> MutexGuard<'_, InputMode> {
         let Self(mutex) = self;
-
-        match mutex.lock() {
-            Ok(guard) => guard,
-            Err(err) => {
-                error!("Lock failed. Reason: {}", err);
-                std::process::exit(1);
-            }
-        }
+        lock_or_exit(mutex)
     }
 
     fn get(&self) -> InputMode {
@@ -75,20 +97,13 @@ impl LoginFormStatusMessage {
         Self(Arc::new(Mutex::new(None)))
     }
 
-    fn get_guard(&self) -> MutexGuard<Option<StatusMessage>> {
+    fn get_guard(&self) -> MutexGuard<'_, Option<StatusMessage>> {
         let Self(mutex) = self;
-
-        match mutex.lock() {
-            Ok(guard) => guard,
-            Err(err) => {
-                error!("Lock failed. Reason: {}", err);
-                std::process::exit(1);
-            }
-        }
+        lock_or_exit(mutex)
     }
 
     fn get(&self) -> Option<StatusMessage> {
-        *self.get_guard()
+        self.get_guard().clone()
     }
 
     fn clear(&self) {
@@ -111,11 +126,25 @@ enum InputMode {
     /// Typing within the Password input field
     Password,
 
+    /// Answering a prompt of PAM during authentication
+    Prompt,
+
+    /// Typing a new password for an expired password
+    NewPassword,
+
+    /// Typing the new password again to confirm it
+    ConfirmPassword,
+
     /// Nothing selected
     Normal,
 }
 
 impl InputMode {
+    /// Whether the mode is typing within a text field
+    fn is_text_field(&self) -> bool {
+        !matches!(self, InputMode::Switcher | InputMode::Normal)
+    }
+
     /// Move to the next mode
     fn next(&mut self) {
         use InputMode::*;
@@ -125,6 +154,9 @@ impl InputMode {
             Switcher => Username,
             Username => Password,
             Password => Password,
+            Prompt => Prompt,
+            NewPassword => ConfirmPassword,
+            ConfirmPassword => ConfirmPassword,
         }
     }
 
@@ -137,52 +169,61 @@ impl InputMode {
             Switcher => Normal,
             Username => Switcher,
             Password => Username,
+            Prompt => Prompt,
+            NewPassword => NewPassword,
+            ConfirmPassword => NewPassword,
         }
     }
 }
 
 enum UIThreadRequest {
     Redraw,
+    /// Sent regularly by the ticker to update what changes over time
+    Tick,
+    /// Show a status message until the instant has passed and redraw it on every tick until
+    /// then, e.g. to count down. It is cleared afterwards, unless it has been replaced.
+    ShowStatusUntil(StatusMessage, Instant),
     DisableTui,
     EnableTui,
     StopDrawing,
 }
 
+/// The fields to choose a new password when the password has expired
+struct NewPasswordFields {
+    new: InputFieldWidget,
+    confirm: InputFieldWidget,
+}
+
 #[derive(Clone)]
 struct Widgets {
     power_menu: PowerMenuWidget,
+    banner: BannerWidget,
+    status_bar: StatusBarWidget,
     environment: Arc<Mutex<SwitcherWidget<PostLoginEnvironment>>>,
     username: Arc<Mutex<InputFieldWidget>>,
     password: Arc<Mutex<InputFieldWidget>>,
+    /// The field for a prompt of PAM, which replaces the password field whilst it is shown
+    prompt: Arc<Mutex<Option<InputFieldWidget>>>,
+    /// The fields for a new password, which replace the username and password fields whilst an
+    /// expired password is changed
+    new_password: Arc<Mutex<Option<NewPasswordFields>>>,
 }
 
 impl Widgets {
-    fn environment_guard(&self) -> MutexGuard<SwitcherWidget<PostLoginEnvironment>> {
-        match self.environment.lock() {
-            Ok(guard) => guard,
-            Err(err) => {
-                error!("Lock failed. Reason: {}", err);
-                std::process::exit(1);
-            }
-        }
+    fn environment_guard(&self) -> MutexGuard<'_, SwitcherWidget<PostLoginEnvironment>> {
+        lock_or_exit(&self.environment)
     }
-    fn username_guard(&self) -> MutexGuard<InputFieldWidget> {
-        match self.username.lock() {
-            Ok(guard) => guard,
-            Err(err) => {
-                error!("Lock failed. Reason: {}", err);
-                std::process::exit(1);
-            }
-        }
+    fn username_guard(&self) -> MutexGuard<'_, InputFieldWidget> {
+        lock_or_exit(&self.username)
     }
-    fn password_guard(&self) -> MutexGuard<InputFieldWidget> {
-        match self.password.lock() {
-            Ok(guard) => guard,
-            Err(err) => {
-                error!("Lock failed. Reason: {}", err);
-                std::process::exit(1);
-            }
-        }
+    fn password_guard(&self) -> MutexGuard<'_, InputFieldWidget> {
+        lock_or_exit(&self.password)
+    }
+    fn prompt_guard(&self) -> MutexGuard<'_, Option<InputFieldWidget>> {
+        lock_or_exit(&self.prompt)
+    }
+    fn new_password_guard(&self) -> MutexGuard<'_, Option<NewPasswordFields>> {
+        lock_or_exit(&self.new_password)
     }
 
     fn get_environment(&self) -> Option<(String, PostLoginEnvironment)> {
@@ -215,6 +256,8 @@ pub struct LoginForm {
 
     widgets: Widgets,
 
+    key_bindings: KeyBindings,
+
     /// The configuration for the app
     config: Config,
 }
@@ -241,36 +284,68 @@ impl LoginForm {
             .then_some(self.widgets.get_username());
 
         info!("Setting cached information");
-        set_cache(selected_env.as_deref(), username.as_deref());
+        set_cache(
+            &self.config.cache_path,
+            selected_env.as_deref(),
+            username.as_deref(),
+        );
     }
 
     fn load_cache(&self) {
         let env_remember = self.config.environment_switcher.remember;
         let username_remember = self.config.username_field.remember;
 
-        let cached = get_cached_information();
+        let cached = get_cached_information(&self.config.cache_path);
 
+        let mut username = None;
         if username_remember {
-            if let Some(username) = cached.username() {
-                info!("Loading username '{}' from cache", username);
-                self.widgets.set_username(username);
+            if let Some(cached_username) = cached.username() {
+                info!("Loading username '{}' from cache", cached_username);
+                self.widgets.set_username(cached_username);
+                username = Some(cached_username);
             }
         }
         if env_remember {
-            if let Some(env) = cached.environment() {
+            let env = username
+                .and_then(|username| cached.user_environment(username))
+                .or(cached.environment());
+
+            if let Some(env) = env {
                 info!("Loading environment '{}' from cache", env);
                 self.widgets.environment_try_select(env);
             }
         }
     }
 
+    /// Select the environment that was last used by the user in the username field
+    fn load_user_environment(&self) {
+        if !self.config.environment_switcher.remember {
+            return;
+        }
+
+        let username = self.widgets.get_username();
+        if let Some(env) =
+            get_cached_information(&self.config.cache_path).user_environment(&username)
+        {
+            info!("Loading environment '{}' of '{}' from cache", env, username);
+            self.widgets.environment_try_select(env);
+        }
+    }
+
     pub fn new(config: Config, preview: bool) -> LoginForm {
+        let key_bindings = KeyBindings::new(&config);
+
         LoginForm {
             preview,
             widgets: Widgets {
-                power_menu: PowerMenuWidget::new(config.power_controls.clone()),
+                banner: BannerWidget::new(config.banner.clone(), config.tty),
+                status_bar: StatusBarWidget::new(config.status_bar.clone(), config.tty),
+                power_menu: PowerMenuWidget::new(
+                    config.power_controls.clone(),
+                    key_bindings.clone(),
+                ),
                 environment: Arc::new(Mutex::new(SwitcherWidget::new(
-                    crate::post_login::get_envs(config.environment_switcher.include_tty_shell)
+                    crate::post_login::get_envs(&config)
                         .into_iter()
                         .map(|(title, content)| SwitcherItem::new(title, content))
                         .collect(),
@@ -291,7 +366,10 @@ impl LoginForm {
                     config.password_field.style.clone(),
                     String::default(),
                 ))),
+                prompt: Arc::new(Mutex::new(None)),
+                new_password: Arc::new(Mutex::new(None)),
             },
+            key_bindings,
             config,
         }
     }
@@ -303,7 +381,13 @@ impl LoginForm {
         start_env_fn: S,
     ) -> io::Result<()>
     where
-        A: Fn(String, String) -> Result<AuthUserInfo<'a>, AuthenticationError>
+        A: Fn(
+                String,
+                String,
+                &str,
+                &Config,
+                Box<dyn Conversation + 'a>,
+            ) -> Result<AuthUserInfo<'a>, AuthenticationError>
             + std::marker::Send
             + 'static,
         S: Fn(&PostLoginEnvironment, &Config, &AuthUserInfo) -> Result<(), EnvironmentStartError>
@@ -332,35 +416,42 @@ impl LoginForm {
         });
         let status_message = LoginFormStatusMessage::new();
 
-        let power_menu = self.widgets.power_menu.clone();
-        let environment = self.widgets.environment.clone();
-        let username = self.widgets.username.clone();
-        let password = self.widgets.password.clone();
-
-        match terminal.draw(|f| {
-            let layout = Chunks::new(f);
-            login_form_render(
-                f,
-                layout,
-                power_menu.clone(),
-                environment.clone(),
-                username.clone(),
-                password.clone(),
-                input_mode.get(),
-                status_message.get(),
-            );
-        }) {
-            Ok(_) => {}
-            Err(err) => {
+        let widgets = self.widgets.clone();
+        let layout_config = self.config.layout.clone();
+        let status_bar_position = self
+            .config
+            .status_bar
+            .show
+            .then_some(self.config.status_bar.position);
+
+        let draw = |terminal: &mut Terminal<CrosstermBackend<io::Stdout>>| {
+            let result = terminal.draw(|f| {
+                login_form_render(
+                    f,
+                    &widgets,
+                    &layout_config,
+                    status_bar_position,
+                    input_mode.get(),
+                    status_message.get(),
+                );
+            });
+
+            if let Err(err) = result {
                 error!("Failed to draw. Reason: {}", err);
                 std::process::exit(1);
             }
-        }
+        };
+
+        draw(terminal);
 
         let event_input_mode = input_mode.clone();
         let event_status_message = status_message.clone();
 
         let (req_send_channel, req_recv_channel) = channel();
+        let auth_req_send_channel = req_send_channel.clone();
+        let ui_stopped = spawn_ticker(req_send_channel.clone());
+        let events = EventReader::new(req_send_channel.clone(), ui_stopped.clone());
+
         std::thread::spawn(move || {
             let input_mode = event_input_mode;
             let status_message = event_status_message;
@@ -370,115 +461,177 @@ impl LoginForm {
                 Err(err) => warn!("Failed to send UI request. Reason: {}", err),
             };
 
-            loop {
-                if let Ok(Event::Key(key)) = event::read() {
-                    match (key.code, input_mode.get()) {
-                        (KeyCode::Enter, InputMode::Password) => {
-                            if self.preview {
-                                // This is only for demonstration purposes
-                                status_message.set(InfoStatusMessage::Authenticating);
-                                send_ui_request(UIThreadRequest::Redraw);
-                                std::thread::sleep(Duration::from_secs(2));
-
-                                status_message.set(InfoStatusMessage::LoggingIn);
-                                send_ui_request(UIThreadRequest::Redraw);
-                                std::thread::sleep(Duration::from_secs(2));
-
-                                status_message.clear();
-                                send_ui_request(UIThreadRequest::Redraw);
-                            } else {
-                                let environment =
-                                    self.widgets.get_environment().map(|(_, content)| content);
-                                let username = self.widgets.get_username();
-                                let password = self.widgets.get_password();
-                                let config = self.config.clone();
-
-                                attempt_login(
-                                    environment,
-                                    username,
-                                    password,
-                                    config,
-                                    status_message.clone(),
-                                    send_ui_request,
-                                    || self.widgets.clear_password(),
-                                    || self.set_cache(),
-                                    &auth_fn,
-                                    &start_env_fn,
-                                );
-                            }
-                        }
-                        (KeyCode::Char('s'), InputMode::Normal) => self.set_cache(),
-                        (KeyCode::Enter | KeyCode::Down, _) => {
-                            input_mode.next();
-                        }
-                        (KeyCode::Up, _) => {
-                            input_mode.prev();
-                        }
-                        (KeyCode::Tab, _) => {
-                            if key.modifiers == KeyModifiers::SHIFT {
-                                input_mode.prev();
-                            } else {
-                                input_mode.next();
-                            }
-                        }
+            while let Some(key) = events.next_key() {
+                let previous_input_mode = input_mode.get();
+                let is = |action| {
+                    self.key_bindings
+                        .is(action, &key, previous_input_mode.is_text_field())
+                };
+
+                match (key.code, previous_input_mode) {
+                    (_, InputMode::Password) if is(Action::Submit) => {
+                        if self.preview {
+                            // This is only for demonstration purposes
+                            status_message.set(InfoStatusMessage::Authenticating);
+                            send_ui_request(UIThreadRequest::Redraw);
+                            std::thread::sleep(Duration::from_secs(2));
+
+                            status_message.set(InfoStatusMessage::LoggingIn);
+                            send_ui_request(UIThreadRequest::Redraw);
+                            std::thread::sleep(Duration::from_secs(2));
+
+                            status_message.clear();
+                            send_ui_request(UIThreadRequest::Redraw);
+                        } else {
+                            let environment =
+                                self.widgets.get_environment().map(|(_, content)| content);
+                            let username = self.widgets.get_username();
+                            let password = self.widgets.get_password();
+                            let config = self.config.clone();
+                            let conversation = TuiConversation {
+                                widgets: self.widgets.clone(),
+                                prompt_style: self.config.password_field.style.clone(),
+                                replacement_character: self
+                                    .config
+                                    .password_field
+                                    .content_replacement_character
+                                    .to_string(),
+                                input_mode: input_mode.clone(),
+                                status_message: status_message.clone(),
+                                ui_requests: auth_req_send_channel.clone(),
+                                events: EventReader::new(
+                                    auth_req_send_channel.clone(),
+                                    ui_stopped.clone(),
+                                ),
+                                key_bindings: self.key_bindings.clone(),
+                            };
 
-                        // Esc is the overal key to get out of your input mode
-                        (KeyCode::Esc, InputMode::Normal) => {
-                            if self.preview {
-                                info!("Pressed escape in preview mode to exit the application");
-                                req_send_channel.send(UIThreadRequest::StopDrawing).unwrap();
-                            }
+                            attempt_login(
+                                environment,
+                                username,
+                                password,
+                                config,
+                                status_message.clone(),
+                                send_ui_request,
+                                || self.widgets.clear_password(),
+                                || self.set_cache(),
+                                Box::new(conversation),
+                                &auth_fn,
+                                &start_env_fn,
+                            );
                         }
-
-                        (KeyCode::Esc, _) => {
-                            input_mode.set(InputMode::Normal);
+                    }
+                    (_, InputMode::Normal) if is(Action::SaveCache) => self.set_cache(),
+                    (_, InputMode::Switcher) if is(Action::SwitcherLeft) => {
+                        self.widgets.environment_guard().left();
+                    }
+                    (_, InputMode::Switcher) if is(Action::SwitcherRight) => {
+                        self.widgets.environment_guard().right();
+                    }
+                    _ if is(Action::NextField) => {
+                        input_mode.next();
+                    }
+                    _ if is(Action::PrevField) => {
+                        input_mode.prev();
+                    }
+                    (_, InputMode::Username) if is(Action::ClearField) => {
+                        self.widgets.username_guard().clear();
+                    }
+                    (_, InputMode::Password) if is(Action::ClearField) => {
+                        self.widgets.clear_password();
+                    }
+
+                    // Esc is the overal key to get out of your input mode
+                    (KeyCode::Esc, InputMode::Normal) => {
+                        if self.preview {
+                            info!("Pressed escape in preview mode to exit the application");
+                            req_send_channel.send(UIThreadRequest::StopDrawing).unwrap();
                         }
-
-                        // For the different input modes the key should be passed to the corresponding
-                        // widget.
-                        (k, mode) => {
-                            let status_message_opt = match mode {
-                                InputMode::Switcher => {
-                                    self.widgets.environment_guard().key_press(k)
-                                }
-                                InputMode::Username => self.widgets.username_guard().key_press(k),
-                                InputMode::Password => self.widgets.password_guard().key_press(k),
-                                InputMode::Normal => self.widgets.power_menu.key_press(k),
-                            };
-
-                            // We don't wanna clear any existing error messages
-                            if let Some(status_msg) = status_message_opt {
-                                status_message.set(status_msg);
-                            }
+                    }
+
+                    (KeyCode::Esc, _) => {
+                        input_mode.set(InputMode::Normal);
+                    }
+
+                    // For the different input modes the key should be passed to the corresponding
+                    // widget.
+                    (k, mode) => {
+                        let status_message_opt = match mode {
+                            InputMode::Switcher => None,
+                            InputMode::Username => self.widgets.username_guard().key_press(k),
+                            InputMode::Password => self.widgets.password_guard().key_press(k),
+                            InputMode::Prompt => self
+                                .widgets
+                                .prompt_guard()
+                                .as_mut()
+                                .and_then(|prompt| prompt.key_press(k)),
+                            InputMode::NewPassword => self
+                                .widgets
+                                .new_password_guard()
+                                .as_mut()
+                                .and_then(|fields| fields.new.key_press(k)),
+                            InputMode::ConfirmPassword => self
+                                .widgets
+                                .new_password_guard()
+                                .as_mut()
+                                .and_then(|fields| fields.confirm.key_press(k)),
+                            InputMode::Normal => self.widgets.power_menu.key_press(key),
+                        };
+
+                        // We don't wanna clear any existing error messages
+                        if let Some(status_msg) = status_message_opt {
+                            status_message.set(status_msg);
                         }
-                    };
+                    }
+                };
+
+                // Jump to the environment the user used last time once the username is
+                // entered
+                if matches!(previous_input_mode, InputMode::Username)
+                    && !matches!(input_mode.get(), InputMode::Username)
+                {
+                    self.load_user_environment();
                 }
 
                 send_ui_request(UIThreadRequest::Redraw);
             }
+
+            // Without events the login form cannot be used anymore. This does nothing if the UI
+            // thread has stopped already.
+            let _ = req_send_channel.send(UIThreadRequest::StopDrawing);
         });
 
         // Start the UI thread. This actually draws to the screen.
         //
         // This blocks until we actually call StopDrawing
+        let mut tui_enabled = true;
+        let mut status_timeout: Option<(StatusMessage, Instant)> = None;
         while let Ok(request) = req_recv_channel.recv() {
             match request {
-                UIThreadRequest::Redraw => {
-                    terminal
-                        .draw(|f| {
-                            let layout = Chunks::new(f);
-                            login_form_render(
-                                f,
-                                layout,
-                                power_menu.clone(),
-                                environment.clone(),
-                                username.clone(),
-                                password.clone(),
-                                input_mode.get(),
-                                status_message.get(),
-                            );
-                        })
-                        .unwrap();
+                UIThreadRequest::Redraw => draw(terminal),
+                // Nothing is drawn over a running session
+                UIThreadRequest::Tick if !tui_enabled => {}
+                UIThreadRequest::Tick => {
+                    let counting_down = status_timeout.is_some();
+                    if status_timeout
+                        .as_ref()
+                        .is_some_and(|(_, until)| *until <= Instant::now())
+                    {
+                        if let Some((message, _)) = status_timeout.take() {
+                            if status_message.get().as_ref() == Some(&message) {
+                                status_message.clear();
+                            }
+                        }
+                    }
+
+                    if counting_down || status_bar_position.is_some() {
+                        draw(terminal);
+                    }
+                }
+                UIThreadRequest::ShowStatusUntil(message, until) => {
+                    status_message.set(message.clone());
+                    status_timeout = Some((message, until));
+                    draw(terminal);
                 }
                 UIThreadRequest::DisableTui => {
                     disable_raw_mode()?;
@@ -489,12 +642,14 @@ impl LoginForm {
                         MoveTo(0, 0)
                     )?;
                     terminal.show_cursor()?;
+                    tui_enabled = false;
                 }
                 UIThreadRequest::EnableTui => {
                     enable_raw_mode()?;
                     let mut stdout = io::stdout();
                     execute!(stdout, EnterAlternateScreen)?;
                     terminal.clear()?;
+                    tui_enabled = true;
                 }
                 _ => break,
             }
@@ -504,51 +659,74 @@ impl LoginForm {
     }
 }
 
-#[allow(clippy::too_many_arguments)]
 fn login_form_render<B: Backend>(
     frame: &mut Frame<B>,
-    chunks: Chunks,
-    power_menu: PowerMenuWidget,
-    environment: Arc<Mutex<SwitcherWidget<PostLoginEnvironment>>>,
-    username: Arc<Mutex<InputFieldWidget>>,
-    password: Arc<Mutex<InputFieldWidget>>,
+    widgets: &Widgets,
+    layout_config: &LayoutConfig,
+    status_bar_position: Option<StatusBarPosition>,
     input_mode: InputMode,
     status_message: Option<StatusMessage>,
 ) {
-    power_menu.render(frame, chunks.power_menu);
-    environment
-        .lock()
-        .unwrap_or_else(|err| {
-            error!("Failed to lock post-login environment. Reason: {}", err);
-            std::process::exit(1);
-        })
-        .render(
+    let chunks = Chunks::new(
+        frame,
+        layout_config,
+        widgets.banner.height(),
+        status_bar_position,
+    );
+
+    if let Some(border) = chunks.border {
+        let style = Style::default().fg(get_color(&layout_config.border_color));
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .border_style(style)
+            .title(Span::styled(layout_config.border_title.as_str(), style));
+        frame.render_widget(block, border);
+    }
+
+    widgets.power_menu.render(frame, chunks.power_menu);
+    widgets.banner.render(frame, chunks.banner);
+    widgets.status_bar.render(frame, chunks.status_bar);
+    widgets.environment_guard().render(
+        frame,
+        chunks.switcher,
+        matches!(input_mode, InputMode::Switcher),
+    );
+
+    if let Some(fields) = widgets.new_password_guard().as_mut() {
+        fields.new.render(
             frame,
-            chunks.switcher,
-            matches!(input_mode, InputMode::Switcher),
+            chunks.username_field,
+            matches!(input_mode, InputMode::NewPassword),
         );
-    username
-        .lock()
-        .unwrap_or_else(|err| {
-            error!("Failed to lock username. Reason: {}", err);
-            std::process::exit(1);
-        })
-        .render(
+        fields.confirm.render(
             frame,
-            chunks.username_field,
-            matches!(input_mode, InputMode::Username),
+            chunks.password_field,
+            matches!(input_mode, InputMode::ConfirmPassword),
         );
-    password
-        .lock()
-        .unwrap_or_else(|err| {
-            error!("Failed to lock password. Reason: {}", err);
-            std::process::exit(1);
-        })
-        .render(
+
+        StatusMessage::render(status_message, frame, chunks.status_message);
+        return;
+    }
+
+    widgets.username_guard().render(
+        frame,
+        chunks.username_field,
+        matches!(input_mode, InputMode::Username),
+    );
+
+    if let Some(prompt) = widgets.prompt_guard().as_mut() {
+        prompt.render(
+            frame,
+            chunks.password_field,
+            matches!(input_mode, InputMode::Prompt),
+        );
+    } else {
+        widgets.password_guard().render(
             frame,
             chunks.password_field,
             matches!(input_mode, InputMode::Password),
         );
+    }
 
     // Display Status Message
     StatusMessage::render(status_message, frame, chunks.status_message);
@@ -564,13 +742,20 @@ fn attempt_login<'a, TR, PC, SC, A, S>(
     send_ui_request: TR,
     password_clear: PC,
     set_cache: SC,
+    conversation: Box<dyn Conversation + 'a>,
     auth_fn: A,
     start_env_fn: S,
 ) where
     TR: Fn(UIThreadRequest),
     PC: Fn(),
     SC: Fn(),
-    A: Fn(String, String) -> Result<AuthUserInfo<'a>, AuthenticationError>,
+    A: Fn(
+        String,
+        String,
+        &str,
+        &Config,
+        Box<dyn Conversation + 'a>,
+    ) -> Result<AuthUserInfo<'a>, AuthenticationError>,
     S: Fn(&PostLoginEnvironment, &Config, &AuthUserInfo) -> Result<(), EnvironmentStartError>,
 {
     // Fetch the selected post login environment
@@ -583,15 +768,61 @@ fn attempt_login<'a, TR, PC, SC, A, S>(
         Some(selected) => selected,
     };
 
+    // Refuse to try logging in whilst the username is throttled because of previous failures
+    let throttling = &config.authentication.throttling;
+    let mut failed_logins = FailedLogins::load(&throttling.failed_logins_path);
+    if let Some(block) = failed_logins.block(&username, throttling, throttle::now()) {
+        info!("Login attempt for '{}' is throttled", username);
+        let until = block_end(&block);
+        send_ui_request(UIThreadRequest::ShowStatusUntil(
+            ErrorStatusMessage::LoginBlocked(block).into(),
+            until,
+        ));
+        return;
+    }
+
     status_message.set(InfoStatusMessage::Authenticating);
     send_ui_request(UIThreadRequest::Redraw);
 
     // Clear the password field
     password_clear();
 
-    let user_info = match auth_fn(username, password) {
+    let user_info = match auth_fn(
+        username.clone(),
+        password,
+        post_login_env.session_type(),
+        &config,
+        conversation,
+    ) {
         Err(err) => {
-            status_message.set(ErrorStatusMessage::AuthenticationError(err));
+            // Only wrong credentials count as a failed attempt. A misconfigured PAM service or a
+            // cancelled prompt is not a guess of the password.
+            let block = if err.is_credential_failure() {
+                let now = throttle::now();
+                failed_logins.record_failure(&username, throttling, now);
+                failed_logins.save(&throttling.failed_logins_path);
+
+                add_btmp_entry(&username, config.tty, &config.login_records);
+
+                failed_logins.block(&username, throttling, now)
+            } else {
+                None
+            };
+
+            // A lockout is shown right away, a short delay only when trying again too early
+            match block {
+                Some(block) if block.locked_out => {
+                    let until = block_end(&block);
+                    send_ui_request(UIThreadRequest::ShowStatusUntil(
+                        ErrorStatusMessage::LoginBlocked(block).into(),
+                        until,
+                    ));
+                }
+                _ => status_message.set(ErrorStatusMessage::authentication(
+                    &err,
+                    &config.authentication,
+                )),
+            }
 
             send_ui_request(UIThreadRequest::Redraw);
 
@@ -600,9 +831,15 @@ fn attempt_login<'a, TR, PC, SC, A, S>(
         Ok(res) => res,
     };
 
+    if failed_logins.record_success(&username) {
+        failed_logins.save(&throttling.failed_logins_path);
+    }
+
     // Remember username for next time
     set_cache();
 
+    update_lastlog(user_info.uid, config.tty, &config.login_records);
+
     status_message.set(InfoStatusMessage::LoggingIn);
     send_ui_request(UIThreadRequest::Redraw);
 
@@ -626,3 +863,208 @@ fn attempt_login<'a, TR, PC, SC, A, S>(
     // Just to add explicitness that the user session is dropped here
     drop(user_info);
 }
+
+/// The instant at which a throttled login is over
+fn block_end(block: &LoginBlock) -> Instant {
+    Instant::now() + Duration::from_secs(block.until.saturating_sub(throttle::now()))
+}
+
+/// Passes the prompts and messages of PAM on to the login form
+///
+/// This is used from the input thread whilst it is authenticating, so it reads the key events
+/// itself.
+struct TuiConversation {
+    widgets: Widgets,
+    prompt_style: InputFieldStyle,
+    replacement_character: String,
+    input_mode: LoginFormInputMode,
+    status_message: LoginFormStatusMessage,
+    ui_requests: Sender<UIThreadRequest>,
+    events: EventReader,
+    key_bindings: KeyBindings,
+}
+
+impl TuiConversation {
+    fn redraw(&self) {
+        if let Err(err) = self.ui_requests.send(UIThreadRequest::Redraw) {
+            warn!("Failed to send UI request. Reason: {}", err);
+        }
+    }
+
+    /// Wait for the next key that is pressed
+    fn read_key(&mut self) -> Result<KeyEvent, ()> {
+        self.events.next_key().ok_or(())
+    }
+
+    fn password_field(&self, title: &str) -> InputFieldWidget {
+        let mut style = self.prompt_style.clone();
+        style.title = title.to_string();
+
+        InputFieldWidget::new(
+            InputFieldDisplayType::Replace(self.replacement_character.clone()),
+            style,
+            String::default(),
+        )
+    }
+
+    /// Show a prompt in place of the password field and wait until it is submitted
+    ///
+    /// The prompt can be cancelled with `Esc`.
+    fn prompt(&mut self, msg: &str, display_type: InputFieldDisplayType) -> Result<String, ()> {
+        let mut style = self.prompt_style.clone();
+        style.title = msg.trim().to_string();
+
+        *self.widgets.prompt_guard() = Some(InputFieldWidget::new(
+            display_type,
+            style,
+            String::default(),
+        ));
+        let previous_input_mode = self.input_mode.get();
+        self.input_mode.set(InputMode::Prompt);
+        self.redraw();
+
+        let answer = loop {
+            let Ok(key) = self.read_key() else {
+                break Err(());
+            };
+
+            match key.code {
+                _ if self.key_bindings.is(Action::Submit, &key, true) => {
+                    break Ok(self
+                        .widgets
+                        .prompt_guard()
+                        .as_ref()
+                        .map(InputFieldWidget::get_content)
+                        .unwrap_or_default());
+                }
+                KeyCode::Esc => {
+                    info!("Cancelled PAM prompt");
+                    break Err(());
+                }
+                _ if self.key_bindings.is(Action::ClearField, &key, true) => {
+                    if let Some(prompt) = self.widgets.prompt_guard().as_mut() {
+                        prompt.clear();
+                    }
+                }
+                k => {
+                    if let Some(prompt) = self.widgets.prompt_guard().as_mut() {
+                        prompt.key_press(k);
+                    }
+                }
+            }
+
+            self.redraw();
+        };
+
+        *self.widgets.prompt_guard() = None;
+        self.input_mode.set(previous_input_mode);
+        self.status_message.set(InfoStatusMessage::Authenticating);
+        self.redraw();
+
+        answer
+    }
+
+    /// Show the fields for a new password in place of the username and password fields and wait
+    /// until a confirmed password is submitted
+    ///
+    /// Changing the password can be cancelled with `Esc`.
+    fn choose_new_password(&mut self) -> Result<String, ()> {
+        *self.widgets.new_password_guard() = Some(NewPasswordFields {
+            new: self.password_field("New password"),
+            confirm: self.password_field("Confirm new password"),
+        });
+        let previous_input_mode = self.input_mode.get();
+        self.input_mode.set(InputMode::NewPassword);
+
+        // Keep showing why a previous new password was rejected
+        if !self
+            .status_message
+            .get()
+            .is_some_and(|status_message| status_message.is_error())
+        {
+            self.status_message.set(InfoStatusMessage::PasswordExpired);
+        }
+        self.redraw();
+
+        let answer = loop {
+            let Ok(key) = self.read_key() else {
+                break Err(());
+            };
+
+            let is = |action| self.key_bindings.is(action, &key, true);
+
+            match (key.code, self.input_mode.get()) {
+                (KeyCode::Esc, _) => {
+                    info!("Cancelled changing the expired password");
+                    break Err(());
+                }
+                (_, InputMode::ConfirmPassword) if is(Action::Submit) => {
+                    let mut fields = self.widgets.new_password_guard();
+                    let Some(fields) = fields.as_mut() else {
+                        break Err(());
+                    };
+
+                    let new_password = fields.new.get_content();
+                    if new_password == fields.confirm.get_content() {
+                        break Ok(new_password);
+                    }
+
+                    fields.new.clear();
+                    fields.confirm.clear();
+                    self.input_mode.set(InputMode::NewPassword);
+                    self.status_message
+                        .set(ErrorStatusMessage::PasswordMismatch);
+                }
+                _ if is(Action::NextField) => self.input_mode.next(),
+                _ if is(Action::PrevField) => self.input_mode.prev(),
+                (k, mode) => {
+                    if let Some(fields) = self.widgets.new_password_guard().as_mut() {
+                        let field = if matches!(mode, InputMode::NewPassword) {
+                            &mut fields.new
+                        } else {
+                            &mut fields.confirm
+                        };
+
+                        if is(Action::ClearField) {
+                            field.clear();
+                        } else {
+                            field.key_press(k);
+                        }
+                    }
+                }
+            }
+
+            self.redraw();
+        };
+
+        *self.widgets.new_password_guard() = None;
+        self.input_mode.set(previous_input_mode);
+        self.status_message.set(InfoStatusMessage::Authenticating);
+        self.redraw();
+
+        answer
+    }
+}
+
+impl Conversation for TuiConversation {
+    fn prompt_echo(&mut self, msg: &str) -> Result<String, ()> {
+        self.prompt(msg, InputFieldDisplayType::Echo)
+    }
+    fn prompt_blind(&mut self, msg: &str) -> Result<String, ()> {
+        let display_type = InputFieldDisplayType::Replace(self.replacement_character.clone());
+        self.prompt(msg, display_type)
+    }
+    fn info(&mut self, msg: &str) {
+        self.status_message
+            .set(InfoStatusMessage::Pam(msg.trim().to_string()));
+        self.redraw();
+    }
+    fn error(&mut self, msg: &str) {
+        self.status_message
+            .set(ErrorStatusMessage::Pam(msg.trim().to_string()));
+        self.redraw();
+    }
+    fn new_password(&mut self) -> Result<String, ()> {
+        self.choose_new_password()
+    }
+}
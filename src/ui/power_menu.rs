@@ -1,22 +1,27 @@
 use std::process::{Command, Output};
 
-use crossterm::event::KeyCode;
+use crossterm::event::KeyEvent;
 use tui::layout::Rect;
 use tui::style::Style;
 use tui::text::{Span, Spans, Text};
 use tui::widgets::Paragraph;
 use tui::Frame;
 
-use crate::config::{get_color, get_key, get_modifiers, PowerControlConfig};
+use super::key_bindings::{Action, KeyBindings};
+use crate::config::{get_color, get_modifiers, PowerControlConfig};
 
 #[derive(Clone)]
 pub struct PowerMenuWidget {
     config: PowerControlConfig,
+    key_bindings: KeyBindings,
 }
 
 impl PowerMenuWidget {
-    pub fn new(config: PowerControlConfig) -> Self {
-        Self { config }
+    pub fn new(config: PowerControlConfig, key_bindings: KeyBindings) -> Self {
+        Self {
+            config,
+            key_bindings,
+        }
     }
     fn shutdown_style(&self) -> Style {
         let mut style = Style::default().fg(get_color(&self.config.shutdown_hint_color));
@@ -45,7 +50,7 @@ impl PowerMenuWidget {
             items.push(Span::styled(
                 self.config
                     .shutdown_hint
-                    .replace("%key%", &self.config.shutdown_key),
+                    .replace("%key%", self.key_bindings.hint(Action::Shutdown)),
                 self.shutdown_style(),
             ));
 
@@ -57,7 +62,7 @@ impl PowerMenuWidget {
             items.push(Span::styled(
                 self.config
                     .reboot_hint
-                    .replace("%key%", &self.config.reboot_key),
+                    .replace("%key%", self.key_bindings.hint(Action::Reboot)),
                 self.reboot_style(),
             ));
         }
@@ -69,9 +74,9 @@ impl PowerMenuWidget {
         frame.render_widget(widget, area);
     }
 
-    pub(crate) fn key_press(&self, key_code: KeyCode) -> Option<super::ErrorStatusMessage> {
+    pub(crate) fn key_press(&self, key: KeyEvent) -> Option<super::ErrorStatusMessage> {
         // TODO: Properly handle StdIn
-        if self.config.allow_shutdown && key_code == get_key(&self.config.shutdown_key) {
+        if self.config.allow_shutdown && self.key_bindings.is(Action::Shutdown, &key, false) {
             let cmd_status = Command::new("bash")
                 .arg("-c")
                 .arg(self.config.shutdown_cmd.clone())
@@ -96,7 +101,7 @@ impl PowerMenuWidget {
                 _ => {}
             }
         }
-        if self.config.allow_reboot && key_code == get_key(&self.config.reboot_key) {
+        if self.config.allow_reboot && self.key_bindings.is(Action::Reboot, &key, false) {
             let cmd_status = Command::new("bash")
                 .arg("-c")
                 .arg(self.config.reboot_cmd.clone())
@@ -0,0 +1,166 @@
+use std::fmt::Write;
+use std::fs::{read_dir, read_to_string};
+use std::path::Path;
+
+use nix::sys::utsname::uname;
+use tui::backend::Backend;
+use tui::layout::{Alignment, Rect};
+use tui::style::Style;
+use tui::text::Text;
+use tui::widgets::Paragraph;
+use tui::Frame;
+
+use crate::config::{
+    get_color, get_modifiers, HorizontalAlignment, StatusBarConfig, StatusBarItem,
+};
+
+const POWER_SUPPLY_PATH: &str = "/sys/class/power_supply";
+const UPTIME_PATH: &str = "/proc/uptime";
+
+/// Format a number of seconds as e.g. `2d 3h 12m`, leaving out the leading units that are zero
+fn format_uptime(seconds: u64) -> String {
+    let minutes = seconds / 60;
+    let (days, hours, minutes) = (minutes / (24 * 60), minutes / 60 % 24, minutes % 60);
+
+    if days > 0 {
+        format!("{}d {}h {}m", days, hours, minutes)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else {
+        format!("{}m", minutes)
+    }
+}
+
+fn uptime() -> Option<String> {
+    let uptime = read_to_string(UPTIME_PATH).ok()?;
+    let seconds: f64 = uptime.split_whitespace().next()?.parse().ok()?;
+
+    Some(format!("up {}", format_uptime(seconds as u64)))
+}
+
+/// The charge of the first battery in a directory like `/sys/class/power_supply`
+fn battery(power_supply_path: &Path) -> Option<String> {
+    let mut supplies: Vec<_> = read_dir(power_supply_path)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    supplies.sort();
+
+    let battery = supplies.into_iter().find(|supply| {
+        read_to_string(supply.join("type")).is_ok_and(|kind| kind.trim() == "Battery")
+    })?;
+
+    let capacity = read_to_string(battery.join("capacity")).ok()?;
+    let capacity = capacity.trim();
+    let status = read_to_string(battery.join("status")).unwrap_or_default();
+
+    Some(match status.trim() {
+        "Charging" => format!("battery {}%, charging", capacity),
+        _ => format!("battery {}%", capacity),
+    })
+}
+
+/// A line with the time and information about the system at the top or bottom of the screen
+#[derive(Clone)]
+pub struct StatusBarWidget {
+    config: StatusBarConfig,
+    tty: u8,
+}
+
+impl StatusBarWidget {
+    pub fn new(config: StatusBarConfig, tty: u8) -> Self {
+        Self { config, tty }
+    }
+
+    /// The text of an item, if it is available on this system
+    fn item_text(&self, item: StatusBarItem) -> Option<String> {
+        match item {
+            StatusBarItem::Clock => {
+                let mut clock = String::new();
+                // An invalid format would otherwise panic
+                write!(
+                    clock,
+                    "{}",
+                    chrono::Local::now().format(&self.config.clock_format)
+                )
+                .ok()?;
+                Some(clock)
+            }
+            StatusBarItem::Hostname => Some(uname().nodename().to_string()),
+            StatusBarItem::Kernel => {
+                let uts = uname();
+                Some(format!("{} {}", uts.sysname(), uts.release()))
+            }
+            StatusBarItem::Uptime => uptime(),
+            StatusBarItem::Tty => Some(format!("tty{}", self.tty)),
+            StatusBarItem::Battery => battery(Path::new(POWER_SUPPLY_PATH)),
+        }
+    }
+
+    fn style(&self) -> Style {
+        let mut style = Style::default().fg(get_color(&self.config.text_color));
+
+        for modifier in get_modifiers(&self.config.text_modifiers) {
+            style = style.add_modifier(modifier);
+        }
+
+        style
+    }
+
+    pub fn render(&self, frame: &mut Frame<impl Backend>, area: Rect) {
+        if area.area() == 0 {
+            return;
+        }
+
+        let text = self
+            .config
+            .items
+            .iter()
+            .filter_map(|item| self.item_text(*item))
+            .collect::<Vec<_>>()
+            .join(&self.config.separator);
+
+        let alignment = match self.config.alignment {
+            HorizontalAlignment::Left => Alignment::Left,
+            HorizontalAlignment::Center => Alignment::Center,
+            HorizontalAlignment::Right => Alignment::Right,
+        };
+
+        let widget = Paragraph::new(Text::raw(text))
+            .style(self.style())
+            .alignment(alignment);
+        frame.render_widget(widget, area);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    #[test]
+    fn uptime_and_battery() {
+        assert_eq!(format_uptime(59), "0m");
+        assert_eq!(format_uptime(3 * 3600 + 12 * 60), "3h 12m");
+        assert_eq!(format_uptime(2 * 86400 + 5 * 60), "2d 0h 5m");
+
+        let dir = std::env::temp_dir().join(format!("lemurs-power-supply-{}", std::process::id()));
+        let _ = remove_dir_all(&dir);
+        assert_eq!(battery(&dir), None);
+
+        create_dir_all(dir.join("AC")).unwrap();
+        write(dir.join("AC/type"), "Mains\n").unwrap();
+        assert_eq!(battery(&dir), None);
+
+        create_dir_all(dir.join("BAT0")).unwrap();
+        write(dir.join("BAT0/type"), "Battery\n").unwrap();
+        write(dir.join("BAT0/capacity"), "85\n").unwrap();
+        write(dir.join("BAT0/status"), "Discharging\n").unwrap();
+        assert_eq!(battery(&dir).as_deref(), Some("battery 85%"));
+
+        write(dir.join("BAT0/status"), "Charging\n").unwrap();
+        assert_eq!(battery(&dir).as_deref(), Some("battery 85%, charging"));
+
+        remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,14 +1,25 @@
+use std::borrow::Cow;
+
 use tui::backend::Backend;
 use tui::layout::Rect;
 use tui::style::Color;
 use tui::widgets::Paragraph;
 use tui::Frame;
 
-use crate::auth::AuthenticationError;
+use crate::auth::throttle::{self, LoginBlock};
+use crate::auth::AuthenticationError as AuthErr;
+use crate::config::AuthenticationConfig;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, PartialEq, Eq)]
 pub enum ErrorStatusMessage {
-    AuthenticationError(AuthenticationError),
+    /// Authentication failed with the message that is shown for the reason
+    AuthenticationError(String),
+    /// An error message sent by a PAM module
+    Pam(String),
+    /// The new password and its confirmation differ
+    PasswordMismatch,
+    /// Logging in is blocked because of previous failed attempts. This shows a countdown.
+    LoginBlocked(LoginBlock),
     NoGraphicalEnvironment,
     FailedGraphicalEnvironment,
     FailedDesktop,
@@ -16,18 +27,60 @@ pub enum ErrorStatusMessage {
     FailedReboot,
 }
 
-impl From<ErrorStatusMessage> for &'static str {
-    fn from(err: ErrorStatusMessage) -> Self {
+impl<'a> From<&'a ErrorStatusMessage> for Cow<'a, str> {
+    fn from(err: &'a ErrorStatusMessage) -> Self {
         use ErrorStatusMessage::*;
 
-        match err {
-            AuthenticationError(_) => "Authentication failed",
+        Cow::Borrowed(match err {
+            AuthenticationError(msg) => msg,
+            Pam(msg) => msg,
+            PasswordMismatch => "Passwords do not match",
+            LoginBlocked(block) => {
+                let remaining = block.until.saturating_sub(throttle::now());
+
+                return Cow::Owned(if block.locked_out {
+                    format!(
+                        "Too many failed attempts. Locked for {}:{:02}",
+                        remaining / 60,
+                        remaining % 60
+                    )
+                } else {
+                    format!("Too many failed attempts. Try again in {}s", remaining)
+                });
+            }
             NoGraphicalEnvironment => "No graphical environment specified",
             FailedGraphicalEnvironment => "Failed booting into the graphical environment",
             FailedDesktop => "Failed booting into desktop environment",
             FailedShutdown => "Failed to shutdown... Check the logs for more information",
             FailedReboot => "Failed to reboot... Check the logs for more information",
-        }
+        })
+    }
+}
+
+impl ErrorStatusMessage {
+    /// The status message for a failed authentication
+    ///
+    /// The reason is only shown when `show_reasons` is enabled in the configuration.
+    pub fn authentication(err: &AuthErr, config: &AuthenticationConfig) -> Self {
+        let messages = &config.messages;
+
+        let msg = match err {
+            AuthErr::PasswordChangeCancelled => "Password change cancelled",
+            AuthErr::PasswordChangeRejected => "New password was rejected",
+            AuthErr::PasswordChangeVerification => "Failed to verify the current password",
+            AuthErr::PasswordChange => "Failed to change the password",
+            AuthErr::Cancelled => "Authentication cancelled",
+            _ if !config.show_reasons => &messages.failed,
+            AuthErr::AccountExpired => &messages.account_expired,
+            AuthErr::AccountLocked => &messages.account_locked,
+            AuthErr::AccessDenied => &messages.access_denied,
+            AuthErr::UsernameNotFound => &messages.unknown_user,
+            AuthErr::SessionOpen => &messages.session,
+            AuthErr::PamService(_) => &messages.pam_service,
+            AuthErr::AccountValidation | AuthErr::Failed(_) => &messages.failed,
+        };
+
+        Self::AuthenticationError(msg.to_string())
     }
 }
 
@@ -37,20 +90,26 @@ impl From<ErrorStatusMessage> for StatusMessage {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, PartialEq, Eq)]
 pub enum InfoStatusMessage {
     LoggingIn,
     Authenticating,
+    /// The password has expired and has to be changed before logging in
+    PasswordExpired,
+    /// An informational message sent by a PAM module
+    Pam(String),
 }
 
-impl From<InfoStatusMessage> for &'static str {
-    fn from(info: InfoStatusMessage) -> Self {
+impl<'a> From<&'a InfoStatusMessage> for Cow<'a, str> {
+    fn from(info: &'a InfoStatusMessage) -> Self {
         use InfoStatusMessage::*;
 
-        match info {
+        Cow::Borrowed(match info {
             LoggingIn => "Authentication successful. Logging in...",
             Authenticating => "Verifying credentials",
-        }
+            PasswordExpired => "Your password has expired. Please choose a new password",
+            Pam(msg) => msg,
+        })
     }
 }
 
@@ -60,14 +119,14 @@ impl From<InfoStatusMessage> for StatusMessage {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, PartialEq, Eq)]
 pub enum StatusMessage {
     Error(ErrorStatusMessage),
     Info(InfoStatusMessage),
 }
 
-impl From<StatusMessage> for &'static str {
-    fn from(msg: StatusMessage) -> Self {
+impl<'a> From<&'a StatusMessage> for Cow<'a, str> {
+    fn from(msg: &'a StatusMessage) -> Self {
         use StatusMessage::*;
 
         match msg {
@@ -85,13 +144,13 @@ impl StatusMessage {
 
     pub fn render<B: Backend>(status: Option<Self>, frame: &mut Frame<B>, area: Rect) {
         if let Some(status_message) = status {
-            let widget = Paragraph::new(<&'static str>::from(status_message)).style(
-                tui::style::Style::default().fg(if status_message.is_error() {
-                    Color::Red
-                } else {
-                    Color::Yellow
-                }),
-            );
+            let color = if status_message.is_error() {
+                Color::Red
+            } else {
+                Color::Yellow
+            };
+            let widget = Paragraph::new(Cow::from(&status_message))
+                .style(tui::style::Style::default().fg(color));
 
             frame.render_widget(widget, area);
         } else {
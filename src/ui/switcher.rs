@@ -1,4 +1,3 @@
-use crossterm::event::KeyCode;
 use log::warn;
 use tui::{
     layout::{Alignment, Rect},
@@ -144,14 +143,14 @@ impl<T> SwitcherWidget<T> {
                 <= area_width
     }
 
-    fn left(&mut self) {
+    pub(crate) fn left(&mut self) {
         let Self {
             ref mut selector, ..
         } = self;
         selector.go_prev();
     }
 
-    fn right(&mut self) {
+    pub(crate) fn right(&mut self) {
         let Self {
             ref mut selector, ..
         } = self;
@@ -169,7 +168,7 @@ impl<T> SwitcherWidget<T> {
 
         let length_difference = usize::from(self.config.max_display_length) - title.len();
         let padding = " ".repeat(length_difference / 2);
-        if length_difference % 2 == 0 {
+        if length_difference.is_multiple_of(2) {
             (padding.clone(), title, padding)
         } else {
             let right_padding = " ".repeat(1 + length_difference / 2);
@@ -363,20 +362,6 @@ impl<T> SwitcherWidget<T> {
         frame.render_widget(widget, area);
     }
 
-    pub(crate) fn key_press(&mut self, key_code: KeyCode) -> Option<super::ErrorStatusMessage> {
-        match key_code {
-            KeyCode::Left | KeyCode::Char('h') => {
-                self.left();
-            }
-            KeyCode::Right | KeyCode::Char('l') => {
-                self.right();
-            }
-            _ => {}
-        }
-
-        None
-    }
-
     pub fn selected(&self) -> Option<&SwitcherItem<T>> {
         let Self { selector, .. } = &self;
         selector.current()